@@ -0,0 +1,445 @@
+/*
+A blueprint lays out a group of nodes and links to be built together.  Placing
+one makes every planned node at once as a ghost: a `Pending` node, linked in
+and taking up its space, that nothing has been sent to build yet.  `Supply`
+then gets each ghost built by whichever factory can reach it, working outward
+from what's already standing - a ghost is only sent for once it's linked to a
+finished node - and queues anything no factory has in stock.
+
+`add_route` plans a chain of nodes between two points too far apart, or too
+obstructed, to link directly: it finds a path of open cells between them, then
+walks it placing each node as far along as a link will reach.
+*/
+
+use std::{
+    cmp::{max, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use hex2d::Coordinate;
+use serde_derive::{Deserialize, Serialize};
+use specs::{
+    prelude::*,
+    storage::BTreeStorage,
+};
+
+use crate::build::{self, Kind};
+use crate::command::Ref;
+use crate::defs::Defs;
+use crate::error::Result;
+use crate::geom;
+use crate::graph;
+use crate::save::{self, Loader, Persist, Saver};
+
+/// One end of a planned link.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum End {
+    /// A node that's already on the map.
+    Node(Ref),
+    /// An index into the blueprint's nodes.
+    Planned(usize),
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Blueprint {
+    pub nodes: Vec<(Kind, save::Coord)>,
+    pub links: Vec<(End, End)>,
+}
+
+impl Blueprint {
+    pub fn is_empty(&self) -> bool { self.nodes.is_empty() && self.links.is_empty() }
+
+    /// Where an end is, and how far it can link.
+    pub fn end(&self, world: &World, end: End) -> Option<(Coordinate, i32)> {
+        match end {
+            End::Node(node) => {
+                let ent = node.get(world)?;
+                let at = world.read_storage::<graph::Node>().get(ent)?.at();
+                let range = world.read_storage::<graph::LinkRange>().get(ent).map_or(0, |r| r.get());
+                Some((at, range))
+            },
+            End::Planned(ix) => {
+                let &(kind, at) = self.nodes.get(ix)?;
+                Some((save::from_coord(at), world.read_resource::<Defs>().kind(kind).link_range))
+            },
+        }
+    }
+
+    /// The planned node, if any, taking up `coord`.
+    pub fn planned_at(&self, coord: Coordinate) -> Option<End> {
+        self.nodes.iter()
+            .position(|&(_, at)| graph::node_space(save::from_coord(at)).contains(&coord))
+            .map(End::Planned)
+    }
+
+    /// Plans a node of `kind` at `at`, linked to `from`.
+    pub fn add_node(&mut self, kind: Kind, at: Coordinate, from: End) -> End {
+        self.nodes.push((kind, save::to_coord(at)));
+        let end = End::Planned(self.nodes.len() - 1);
+        self.links.push((from, end));
+        end
+    }
+
+    pub fn add_link(&mut self, a: End, b: End) { self.links.push((a, b)) }
+
+    pub fn can_add_node(&self, world: &World, kind: Kind, at: Coordinate, from: End) -> bool {
+        let mut with = self.clone();
+        with.add_node(kind, at, from);
+        with.valid(world)
+    }
+
+    pub fn can_add_link(&self, world: &World, a: End, b: End) -> bool {
+        if a == b || self.links.iter().any(|&l| l == (a, b) || l == (b, a)) { return false }
+        if let (End::Node(a), End::Node(b)) = (a, b) {
+            if let (Some(a), Some(b)) = (a.get(world), b.get(world)) {
+                if world.read_storage::<graph::Node>().get(a).map_or(false, |n| n.link_to(b).is_some()) {
+                    return false
+                }
+            }
+        }
+        let mut with = self.clone();
+        with.add_link(a, b);
+        with.valid(world)
+    }
+
+    /// Whether everything fits: each node and link has room on the map and
+    /// doesn't overlap the rest of the plan, and each link is in range.
+    pub fn valid(&self, world: &World) -> bool { self.claims(world).is_some() }
+
+    /// The cells the plan takes up, if it's valid.
+    fn claims(&self, world: &World) -> Option<HashSet<Coordinate>> {
+        let defs = world.read_resource::<Defs>();
+        let map = world.read_resource::<geom::Map>();
+        let mut claimed = HashSet::new();
+        for &(kind, at) in &self.nodes {
+            defs.kind(kind).cost.as_ref()?;
+            let at = save::from_coord(at);
+            if !graph::space_for_node(&map, at) { return None }
+            for c in graph::node_space(at) {
+                if !claimed.insert(c) { return None }
+            }
+        }
+        for &(a, b) in &self.links {
+            let ((a_at, a_range), (b_at, b_range)) = (self.end(world, a)?, self.end(world, b)?);
+            if a_at == b_at || max(a_range, b_range) < a_at.distance(b_at) { return None }
+            if !graph::space_for_link(&map, a_at, b_at) { return None }
+            for c in graph::link_shape(a_at, b_at) {
+                if !claimed.insert(c) { return None }
+            }
+        }
+        Some(claimed)
+    }
+
+    /// Plans a chain of `kind` nodes linking `from` to `to`, steering around
+    /// whatever's in the way.  Returns false, leaving the plan as it was, if
+    /// there's no way through.
+    pub fn add_route(&mut self, world: &World, kind: Kind, from: End, to: End) -> bool {
+        if from == to { return false }
+        let path = match self.find_path(world, from, to) { Some(p) => p, None => return false };
+        let kind_range = world.read_resource::<Defs>().kind(kind).link_range;
+        let mut plan = self.clone();
+        let (mut at, mut ix) = (from, 0);
+        loop {
+            if plan.can_add_link(world, at, to) {
+                plan.add_link(at, to);
+                *self = plan;
+                return true
+            }
+            let (at_coord, at_range) = if let Some(e) = plan.end(world, at) { e } else { return false };
+            let reach = max(at_range, kind_range);
+            // The furthest along the path that a node can go and still link back.
+            let next = (ix + 1..path.len() - 1).rev()
+                .filter(|&n| at_coord.distance(path[n]) <= reach)
+                .find(|&n| plan.can_add_node(world, kind, path[n], at));
+            match next {
+                Some(n) => {
+                    at = plan.add_node(kind, path[n], at);
+                    ix = n;
+                },
+                None => return false,
+            }
+        }
+    }
+
+    /// A path of cells from one end to the other that's clear of the map and
+    /// the plan, preferring open ground where nodes will fit.
+    fn find_path(&self, world: &World, from: End, to: End) -> Option<Vec<Coordinate>> {
+        let claimed = self.claims(world)?;
+        let (start, _) = self.end(world, from)?;
+        let (goal, _) = self.end(world, to)?;
+        let map = world.read_resource::<geom::Map>();
+        let ends: HashSet<Coordinate> = graph::node_space(start).into_iter()
+            .chain(graph::node_space(goal))
+            .collect();
+        let open = |c: Coordinate| ends.contains(&c) || (map.get(c).is_none() && !claimed.contains(&c));
+        let cramped = |c: Coordinate| c.neighbors().iter().any(|&n| !open(n));
+
+        let mut cost: HashMap<Coordinate, usize> = HashMap::new();
+        let mut came_from: HashMap<Coordinate, Coordinate> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        cost.insert(start, 0);
+        queue.push(Reverse((start.distance(goal) as usize, start.x, start.y)));
+        let mut searched = 0;
+        while let Some(Reverse((_, x, y))) = queue.pop() {
+            let here = Coordinate::new(x, y);
+            if here == goal {
+                let mut path = vec![goal];
+                let mut c = goal;
+                while let Some(&prev) = came_from.get(&c) {
+                    path.push(prev);
+                    c = prev;
+                }
+                path.reverse();
+                return Some(path)
+            }
+            searched += 1;
+            if searched > PATH_SEARCH_LIMIT { return None }
+            let here_cost = cost[&here];
+            for &next in here.neighbors().iter() {
+                if !open(next) { continue }
+                let step = if cramped(next) { CRAMPED_COST } else { 1 };
+                let next_cost = here_cost + step;
+                if cost.get(&next).map_or(false, |&c| c <= next_cost) { continue }
+                cost.insert(next, next_cost);
+                came_from.insert(next, here);
+                let estimate = next_cost + next.distance(goal) as usize;
+                queue.push(Reverse((estimate, next.x, next.y)));
+            }
+        }
+        None
+    }
+
+    /// Makes the ghost nodes and the links.  Returns the new nodes and links,
+    /// or `None` if the plan no longer fits.
+    pub fn place(&self, world: &mut World) -> Option<(Vec<Entity>, Vec<Entity>)> {
+        if self.is_empty() || !self.valid(world) { return None }
+        let mut made = vec![];
+        for &(kind, at) in &self.nodes {
+            let node = graph::make_node(world, save::from_coord(at));
+            world.write_storage().insert(node, build::Pending).unwrap();
+            world.write_storage().insert(node, Planned { kind, ordered: None }).unwrap();
+            made.push(node);
+        }
+        // Checked by `valid`.
+        let ent = |world: &World, end: End| match end {
+            End::Node(node) => node.get(world).unwrap(),
+            End::Planned(ix) => made[ix],
+        };
+        let mut links = vec![];
+        for &(a, b) in &self.links {
+            let (a, b) = (ent(world, a), ent(world, b));
+            links.push(graph::make_link(world, a, b));
+        }
+        Some((made, links))
+    }
+}
+
+/// How many cells `find_path` looks at before giving up.
+const PATH_SEARCH_LIMIT: usize = 20000;
+/// The cost of stepping through a cell next to something, where there's no
+/// room for a node.
+const CRAMPED_COST: usize = 3;
+
+/// A ghost node waiting for a factory to send its build.
+#[derive(Debug, Clone)]
+pub struct Planned {
+    kind: Kind,
+    /// The factory asked to make one for it.
+    ordered: Option<Entity>,
+}
+
+impl Component for Planned {
+    type Storage = BTreeStorage<Self>;
+}
+
+impl Planned {
+    /// The factory asked to make one, and what.
+    pub fn order(&self) -> Option<(Entity, Kind)> { Some((self.ordered?, self.kind)) }
+}
+
+impl Persist for Planned {
+    type Data = (Kind, Option<u32>);
+    fn save(&self, saver: &Saver) -> Result<Self::Data> {
+        Ok((self.kind, match self.ordered { Some(f) => Some(saver.id(f)?), None => None }))
+    }
+    fn load((kind, ordered): Self::Data, loader: &Loader) -> Result<Self> {
+        Ok(Planned {
+            kind: loader.kind(kind)?,
+            ordered: match ordered { Some(f) => Some(loader.entity(f)?), None => None },
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Supply;
+
+#[derive(shred_derive::SystemData)]
+pub struct SupplyData<'a> {
+    entities: Entities<'a>,
+    lazy: Write<'a, LazyUpdate>,
+    nodes: ReadStorage<'a, graph::Node>,
+    links: ReadStorage<'a, graph::Link>,
+    pending: ReadStorage<'a, build::Pending>,
+    planned: WriteStorage<'a, Planned>,
+    factories: WriteStorage<'a, build::Factory>,
+    graphs: WriteStorage<'a, graph::AreaGraph>,
+}
+
+impl<'a> System<'a> for Supply {
+    type SystemData = SupplyData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let mut sent = vec![];
+        let (nodes, links, pending) = (&data.nodes, &data.links, &data.pending);
+        for (node_ent, node, plan) in (&*data.entities, nodes, &mut data.planned).join() {
+            // Build outward from what's finished.
+            if !node.neighbors().any(|n| nodes.get(n).is_some() && pending.get(n).is_none()) {
+                continue
+            }
+            let kind = plan.kind;
+            let mut from = None;
+            let mut can_order = None;
+            for (factory_ent, factory, ag) in (&*data.entities, &mut data.factories, &mut data.graphs).join() {
+                let stocked = factory.built(kind) > 0;
+                let builds = factory.can_build().contains(&kind);
+                if !stocked && (!builds || can_order.is_some()) { continue }
+                let (_, mut router) = ag.nodes_route();
+                let route = if let Some((_, r)) = router.route(links, nodes, factory_ent, node_ent) { r }
+                    else { continue };
+                if stocked {
+                    factory.dec_built(kind).unwrap();
+                    from = Some((factory_ent, route));
+                    break
+                }
+                can_order = Some(factory_ent);
+            }
+            if let Some((factory_ent, route)) = from {
+                sent.push(node_ent);
+                data.lazy.exec_mut(move |world| kind.send(world, factory_ent, node_ent, route));
+                continue
+            }
+            // Nothing in stock; make sure one's on the way.
+            let factories = &data.factories;
+            let coming = plan.ordered.and_then(|f| factories.get(f)).map_or(false, |f| {
+                f.building() == Some(kind) || f.queue().iter().any(|o| o.kind == kind)
+            });
+            if coming { continue }
+            if let Some(f) = can_order {
+                data.factories.get_mut(f).unwrap().queue_push(kind);
+                plan.ordered = Some(f);
+            }
+        }
+        for node in sent {
+            data.planned.remove(node);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{self, Command};
+
+    fn count(world: &World) -> (usize, usize) {
+        (world.read_storage::<graph::Node>().join().count(), world.read_storage::<graph::Link>().join().count())
+    }
+
+    #[test]
+    fn clear_takes_links_back() {
+        let mut world = crate::test_world();
+        let seed = (&*world.entities(), &world.read_storage::<graph::Node>()).join().next().unwrap().0;
+        let other = graph::make_node(&mut world, Coordinate { x: 5, y: 0 });
+        let strut = world.read_resource::<Defs>().find("Strut").unwrap();
+        let mut plan = Blueprint::default();
+        plan.add_node(strut, Coordinate { x: 0, y: 5 }, End::Node(seed.into()));
+        plan.add_link(End::Node(seed.into()), End::Node(other.into()));
+        assert!(plan.valid(&world));
+
+        command::issue(&mut world, Command::PlaceBlueprint { blueprint: plan });
+        assert_eq!(count(&world), (3, 2));
+        command::issue(&mut world, Command::Undo);
+        assert_eq!(count(&world), (2, 0));
+        // Nothing left behind in the way of placing it again.
+        command::issue(&mut world, Command::Redo);
+        assert_eq!(count(&world), (3, 2));
+    }
+
+    #[test]
+    fn clear_takes_orders_back() {
+        let mut world = crate::test_world();
+        let mut update = crate::make_update();
+        let seed = seed(&world);
+        // Nothing in stock, so the seed is asked to make one.
+        let storage = world.read_resource::<Defs>().find("Storage").unwrap();
+        let mut plan = Blueprint::default();
+        plan.add_node(storage, Coordinate { x: 0, y: 5 }, End::Node(seed.into()));
+        command::issue(&mut world, Command::PlaceBlueprint { blueprint: plan });
+        let queued = |world: &World| world.read_storage::<build::Factory>().get(seed).unwrap().queue().len();
+        for _ in 0..10 { crate::step(&mut world, &mut update); }
+        assert_eq!(queued(&world), 1);
+
+        command::issue(&mut world, Command::Undo);
+        assert_eq!(queued(&world), 0);
+    }
+
+    fn seed(world: &World) -> Entity {
+        (&*world.entities(), &world.read_storage::<graph::Node>()).join().next().unwrap().0
+    }
+
+    #[test]
+    fn path_steers_around_nodes() {
+        let mut world = crate::test_world();
+        let from = End::Node(seed(&world).into());
+        let to = End::Node(graph::make_node(&mut world, Coordinate { x: 30, y: 0 }).into());
+        let plan = Blueprint::default();
+        let check = |path: &[Coordinate]| {
+            assert_eq!(path.first(), Some(&Coordinate { x: 0, y: 0 }));
+            assert_eq!(path.last(), Some(&Coordinate { x: 30, y: 0 }));
+            assert!(path.windows(2).all(|w| w[0].distance(w[1]) == 1));
+        };
+        // Open ground: straight there.
+        let path = plan.find_path(&world, from, to).unwrap();
+        check(&path);
+        assert_eq!(path.len(), 31);
+
+        let blocker = Coordinate { x: 15, y: 0 };
+        graph::make_node(&mut world, blocker);
+        let path = plan.find_path(&world, from, to).unwrap();
+        check(&path);
+        assert!(path.len() > 31);
+        assert!(path.iter().all(|c| !graph::node_space(blocker).contains(c)));
+    }
+
+    #[test]
+    fn route_links_up() {
+        let mut world = crate::test_world();
+        let seed = seed(&world);
+        let far = graph::make_node(&mut world, Coordinate { x: 30, y: 0 });
+        let strut = world.read_resource::<Defs>().find("Strut").unwrap();
+        let mut plan = Blueprint::default();
+        assert!(plan.add_route(&world, strut, End::Node(seed.into()), End::Node(far.into())));
+        assert!(plan.valid(&world));
+        assert!(!plan.nodes.is_empty());
+        assert_eq!(plan.links.len(), plan.nodes.len() + 1);
+        assert_eq!(plan.links.last().unwrap().1, End::Node(far.into()));
+
+        let nodes = plan.nodes.len();
+        command::issue(&mut world, Command::PlaceBlueprint { blueprint: plan });
+        assert_eq!(count(&world), (2 + nodes, nodes + 1));
+    }
+
+    #[test]
+    fn failed_route_leaves_plan() {
+        let mut world = crate::test_world();
+        let from = End::Node(seed(&world).into());
+        let to = End::Node(graph::make_node(&mut world, Coordinate { x: 30, y: 0 }).into());
+        let strut = world.read_resource::<Defs>().find("Strut").unwrap();
+        let mut plan = Blueprint::default();
+        assert!(!plan.add_route(&world, strut, from, from));
+        // A kind with no cost can't be planned, so nothing fits.
+        let start = world.read_resource::<Defs>().start();
+        plan.add_node(start, Coordinate { x: 0, y: -10 }, from);
+        assert!(!plan.add_route(&world, strut, from, to));
+        assert_eq!((plan.nodes.len(), plan.links.len()), (1, 1));
+    }
+}
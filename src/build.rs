@@ -1,173 +1,176 @@
-use std::{
-    collections::{HashMap, HashSet, VecDeque},
-    time::Duration,
-};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use hex2d::Coordinate;
+use serde_derive::{Deserialize, Serialize};
 use specs::{
     prelude::*,
     storage::BTreeStorage,
 };
 
+use crate::defs::Defs;
 use crate::error::{Error, Result, or_die};
 use crate::graph;
 use crate::power::{self, Power};
 use crate::reactor::{Progress, Reactor};
-use crate::resource::{
-    self,
-    Pool, Resource,
-};
-use crate::util;
+use crate::resource::{self, Resource};
+use crate::save::{Loader, Persist, Saver};
 
-#[derive(Debug, Default)]
+pub use crate::defs::Kind;
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Pending;
 
 impl Component for Pending {
     type Storage = NullStorage<Self>;
 }
 
-#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
-pub enum Kind {
-    // Structure
-    Strut,
-    // Reactors
-    CarbonSource,
-    WaterSource,
-    #[allow(unused)]
-    Electrolysis,
-    /*
-    // Power
-    PowerSource,
-    Pylon,
-    // Other
-    Storage,
-    */
-    Seed,
-}
-
 #[derive(Debug, Clone)]
 pub struct Packet {
     kind: Kind,
     target: Entity,
 }
 
+impl Packet {
+    pub fn kind(&self) -> Kind { self.kind }
+    pub fn target(&self) -> Entity { self.target }
+}
+
 impl Component for Packet {
     type Storage = BTreeStorage<Self>;
 }
 
-const REACTION_TIME: Duration = Duration::from_millis(5000);
-const REACTOR_RANGE: i32 = 20;
+impl Persist for Packet {
+    type Data = (Kind, u32);
+    fn save(&self, saver: &Saver) -> Result<Self::Data> {
+        Ok((self.kind, saver.id(self.target)?))
+    }
+    fn load((kind, target): Self::Data, loader: &Loader) -> Result<Self> {
+        Ok(Packet { kind: loader.kind(kind)?, target: loader.entity(target)? })
+    }
+}
+
+/// A built item on its way from one factory to be stocked at another.
+#[derive(Debug, Clone)]
+pub struct Delivery {
+    kind: Kind,
+    factory: Entity,
+}
+
+impl Delivery {
+    pub fn kind(&self) -> Kind { self.kind }
+    pub fn factory(&self) -> Entity { self.factory }
+}
+
+impl Component for Delivery {
+    type Storage = BTreeStorage<Self>;
+}
+
+impl Persist for Delivery {
+    type Data = (Kind, u32);
+    fn save(&self, saver: &Saver) -> Result<Self::Data> {
+        Ok((self.kind, saver.id(self.factory)?))
+    }
+    fn load((kind, factory): Self::Data, loader: &Loader) -> Result<Self> {
+        Ok(Delivery { kind: loader.kind(kind)?, factory: loader.entity(factory)? })
+    }
+}
+
 const PACKET_SPEED: f32 = 2.0;
-const LINK_RANGE: i32 = 6;
+
+/// Plans a route from factory `start` to `to` in its area graph.
+fn route_from(world: &World, start: Entity, to: Entity) -> Option<graph::Route> {
+    let mut areas = world.write_storage::<graph::AreaGraph>();
+    let ag = areas.get_mut(start)?;
+    let (_, mut router) = ag.nodes_route();
+    router.route(&world.read_storage(), &world.read_storage(), start, to).map(|(_, route)| route)
+}
+
+/// Sends one of factory `from`'s built `kind` to factory `to`.  Returns false,
+/// having sent nothing, if `from` has none ready or `to` is out of its reach.
+pub fn deliver(world: &mut World, kind: Kind, from: Entity, to: Entity) -> bool {
+    if from == to || world.read_storage::<Factory>().get(to).is_none() { return false }
+    let route = if let Some(r) = route_from(world, from, to) { r } else { return false };
+    let sent = world.write_storage::<Factory>().get_mut(from)
+        .map_or(false, |f| f.dec_built(kind).is_ok());
+    if !sent { return false }
+    let packet = world.create_entity()
+        .with(Delivery { kind, factory: to })
+        .build();
+    graph::Traverse::start(world, packet, from, from, to, route, PACKET_SPEED);
+    true
+}
 
 impl Kind {
-    pub fn make(&self, world: &mut World, entity: Entity) {
-        use self::Kind::*;
+    pub fn make(self, world: &mut World, entity: Entity) {
+        let def = world.read_resource::<Defs>().kind(self).clone();
         // Function
-        match self {
-            Strut => (),
-            CarbonSource => Reactor::add(
-                world, entity,
-                /* input=  */ Pool::from(vec![]),
-                /* delay=  */ REACTION_TIME,
-                /* output= */ Pool::from(vec![(Resource::C, 1)]),
-                /* power=  */ -100.0,  // kJ/mol
-                /* range=  */ REACTOR_RANGE,
-            ),
-            WaterSource => Reactor::add(
-                world, entity,
-                /* input=  */ Pool::from(vec![]),
-                /* delay=  */ REACTION_TIME,
-                /* output= */ Pool::from(vec![(Resource::H2O, 1)]),
-                /* power=  */ -100.0,  // kJ/mol
-                /* range=  */ REACTOR_RANGE,
-            ),
-            Electrolysis => Reactor::add(
-                world, entity,
-                /* input=  */ Pool::from(vec![(Resource::H2O, 2)]),
-                /* delay=  */ REACTION_TIME,
-                /* output= */ Pool::from(vec![(Resource::O2, 1), (Resource::H2, 2)]),
-                /* power=  */ -3242.0,  // kJ/mol
-                /* range=  */ REACTOR_RANGE,
-            ),
-            Seed => {
-                power::Pylon::add(world, entity, /* range= */ 20);
-                Factory::add(world, entity,
-                    vec![Strut, CarbonSource, WaterSource],
-                    /* range= */ 20);
-                world.write_storage::<Power>().get_mut(entity).unwrap()
-                    .set::<()>(100.0);
-                world.write_storage::<Factory>().get_mut(entity).unwrap()
-                    .inc_built(CarbonSource);
+        if let Some(range) = def.pylon {
+            power::Pylon::add(world, entity, range);
+        }
+        if let Some(factory) = &def.factory {
+            Factory::add(world, entity, factory.builds.iter().cloned(), factory.range);
+            let mut factories = world.write_storage::<Factory>();
+            let f = factories.get_mut(entity).unwrap();
+            for &(kind, count) in &factory.built {
+                for _ in 0..count { f.inc_built(kind); }
             }
         }
-        // Link range
-        let lr = match self {
-            Strut => LINK_RANGE*2,
-            _ => LINK_RANGE,
-        };
-        world.write_storage().insert(entity, graph::LinkRange::new(lr)).unwrap();
-    }
-    fn cost(&self) -> (Pool, /*power=*/ f32, Duration) {
-        use self::Kind::*;
-        match self {
-            Strut => (
-                Pool::from(vec![(Resource::C, 2)]), -100.0,
-                Duration::from_millis(10000),
-            ),
-            CarbonSource => (
-                Pool::from(vec![(Resource::C, 2)]), -100.0,
-                Duration::from_millis(10000),
-            ),
-            WaterSource => (
-                Pool::from(vec![(Resource::C, 2)]), -100.0,
-                Duration::from_millis(10000),
-            ),
-            Electrolysis => (
-                Pool::from(vec![(Resource::C, 2)]), -100.0,
-                Duration::from_millis(10000),
-            ),
-            Seed => panic!("Seed is pre-built"),
+        if let Some(reactor) = &def.reactor {
+            Reactor::add(world, entity, reactor);
+        }
+        if let Some(storage) = &def.storage {
+            resource::Storage::add(world, entity, &storage.caps, storage.range);
+        }
+        if let Some(battery) = &def.battery {
+            power::Battery::add(world, entity, battery.capacity, battery.rate);
+        }
+        if let Some(power) = def.power {
+            Power::add(world, entity);
+            world.write_storage::<Power>().get_mut(entity).unwrap().set::<()>(power);
         }
+        // Link range
+        world.write_storage().insert(entity, graph::LinkRange::new(def.link_range)).unwrap();
     }
-    pub fn start(&self, world: &mut World, start: Entity, fork: Entity, location: Coordinate) {
+    /// Sends a build packet from `start` to a new pending node at `location`,
+    /// linked to `fork`.  Returns the new node.
+    pub fn start(self, world: &mut World, start: Entity, fork: Entity, location: Coordinate) -> Entity {
         let node = graph::make_node(world, location);
         or_die(|| {
             world.write_storage().insert(node, Pending)?;
             graph::make_link(world, fork, node);
-            let packet = world.create_entity()
-                .with(Packet { kind: *self, target: node })
-                .build();
-            let route = {
-                let mut areas = world.write_storage();
-                let ag: &mut graph::AreaGraph = util::try_get_mut(&mut areas, start)?;
-                let (_, mut router) = ag.nodes_route();
-                let (_, route) = router.route(
-                    &world.read_storage(), &world.read_storage(),
-                    start, node,
-                ).ok_or(Error::NoPath)?;
-                route
-            };
-            let start_coord = util::try_get(&world.read_storage::<graph::Node>(), start)?.at();
-            graph::Traverse::start(world, packet, start_coord, route, PACKET_SPEED);
+            let route = route_from(world, start, node).ok_or(Error::NoPath)?;
+            self.send(world, start, node, route);
             Ok(())
         });
+        node
+    }
+    /// Sends a build packet from factory `start` along `route` to the pending
+    /// `node`.
+    pub fn send(self, world: &mut World, start: Entity, node: Entity, route: graph::Route) {
+        let packet = world.create_entity()
+            .with(Packet { kind: self, target: node })
+            .build();
+        graph::Traverse::start(world, packet, start, start, node, route, PACKET_SPEED);
     }
 }
 
 #[derive(Debug)]
 pub struct Build;
 
+type BuildData<'a> = (
+    Write<'a, LazyUpdate>,
+    Entities<'a>,
+    ReadStorage<'a, graph::RouteDone>,
+    ReadStorage<'a, Packet>,
+    WriteStorage<'a, Pending>,
+    ReadStorage<'a, Delivery>,
+    WriteStorage<'a, Factory>,
+);
+
 impl<'a> System<'a> for Build {
-    type SystemData = (
-        Read<'a, LazyUpdate>,
-        Entities<'a>,
-        ReadStorage<'a, graph::RouteDone>,
-        ReadStorage<'a, Packet>,
-        WriteStorage<'a, Pending>,
-    );
+    type SystemData = BuildData<'a>;
 
-    fn run(&mut self, (lazy, entities, route_done, packets, mut pending): Self::SystemData) {
+    fn run(&mut self, (lazy, entities, route_done, packets, mut pending, deliveries, mut factories): Self::SystemData) {
         for (entity, _, packet) in (&*entities, &route_done, &packets).join() {
             pending.remove(packet.target);
             entities.delete(entity).unwrap();
@@ -176,14 +179,29 @@ impl<'a> System<'a> for Build {
                 packet.kind.make(world, packet.target);
             });
         }
+        for (entity, _, delivery) in (&*entities, &route_done, &deliveries).join() {
+            entities.delete(entity).unwrap();
+            if let Some(factory) = factories.get_mut(delivery.factory) {
+                factory.inc_built(delivery.kind);
+            }
+        }
     }
 }
 
+/// A queued build.  Each time one is started, an order with more to go moves
+/// to the back of the queue, so repeating orders take turns.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Order {
+    pub kind: Kind,
+    /// How many more to build; `None` repeats forever.
+    pub count: Option<usize>,
+}
+
 #[derive(Debug)]
 pub struct Factory {
     can_build: HashSet<Kind>,
     built: HashMap<Kind, usize>,
-    queue: VecDeque<Kind>,
+    queue: VecDeque<Order>,
     building: Option<Kind>,
 }
 
@@ -195,7 +213,7 @@ impl Factory {
         or_die(|| {
             graph::AreaGraph::add(world, entity, range)?;
             world.write_storage().insert(entity, resource::Sink::new())?;
-            world.write_storage().insert(entity, Power::new())?;
+            Power::add(world, entity);
             world.write_storage().insert(entity, Progress::new())?;
             world.write_storage().insert(entity, Factory {
                 can_build: can_build.into_iter().collect(),
@@ -207,6 +225,14 @@ impl Factory {
         });
     }
     pub fn can_build(&self) -> &HashSet<Kind> { &self.can_build }
+    /// Everything it can build or has ready to place, in order.
+    pub fn kinds(&self) -> Vec<Kind> {
+        let mut kinds: Vec<Kind> = self.can_build.iter().cloned()
+            .chain(self.built.iter().filter(|&(k, &n)| n > 0 && !self.can_build.contains(k)).map(|(&k, _)| k))
+            .collect();
+        kinds.sort();
+        kinds
+    }
     pub fn built(&self, kind: Kind) -> usize { *self.built.get(&kind).unwrap_or(&0) }
     pub fn inc_built(&mut self, kind: Kind) {
          let count = self.built.entry(kind).or_insert(0);
@@ -220,26 +246,100 @@ impl Factory {
         self.built.insert(kind, has-1);
         Ok(())
     }
-    pub fn queue(&self) -> &VecDeque<Kind> { &self.queue }
-    pub fn queue_push(&mut self, kind: Kind) { self.queue.push_back(kind) }
+    pub fn queue(&self) -> &VecDeque<Order> { &self.queue }
+    pub fn queue_push(&mut self, kind: Kind) { self.queue.push_back(Order { kind, count: Some(1) }) }
+    /// Takes the last order for `kind` back off the queue, if it's there.
+    pub fn unqueue(&mut self, kind: Kind) -> bool {
+        match self.queue.iter().rposition(|o| o.kind == kind) {
+            Some(ix) => { self.queue.remove(ix); true },
+            None => false,
+        }
+    }
+    pub fn insert_order(&mut self, ix: usize, order: Order) -> bool {
+        if ix > self.queue.len() { return false }
+        self.queue.insert(ix, order);
+        true
+    }
+    pub fn remove_order(&mut self, ix: usize) -> Option<Order> { self.queue.remove(ix) }
+    pub fn move_order(&mut self, from: usize, to: usize) -> bool {
+        if from >= self.queue.len() || to >= self.queue.len() { return false }
+        let order = self.queue.remove(from).unwrap();
+        self.queue.insert(to, order);
+        true
+    }
+    /// Returns false if there's no order at `ix`.
+    pub fn set_count(&mut self, ix: usize, count: Option<usize>) -> bool {
+        match self.queue.get_mut(ix) {
+            Some(o) => { o.count = count; true },
+            None => false,
+        }
+    }
+    pub fn building(&self) -> Option<Kind> { self.building }
+    /// Stops building; whoever calls this has the cost to refund.
+    pub fn cancel_building(&mut self) -> Option<Kind> { self.building.take() }
 }
 
 impl Component for Factory {
     type Storage = BTreeStorage<Self>;
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct FactoryData {
+    can_build: Vec<Kind>,
+    built: Vec<(Kind, usize)>,
+    queue: Vec<Order>,
+    building: Option<Kind>,
+}
+
+impl Persist for Factory {
+    type Data = FactoryData;
+    fn save(&self, _: &Saver) -> Result<FactoryData> {
+        let mut can_build: Vec<Kind> = self.can_build.iter().cloned().collect();
+        can_build.sort();
+        let mut built: Vec<(Kind, usize)> = self.built.iter().map(|(&k, &c)| (k, c)).collect();
+        built.sort();
+        Ok(FactoryData {
+            can_build, built,
+            queue: self.queue.iter().cloned().collect(),
+            building: self.building,
+        })
+    }
+    fn load(data: FactoryData, loader: &Loader) -> Result<Self> {
+        let mut built = HashMap::new();
+        for (kind, count) in data.built { built.insert(loader.kind(kind)?, count); }
+        Ok(Factory {
+            can_build: data.can_build.into_iter().map(|k| loader.kind(k)).collect::<Result<_>>()?,
+            built,
+            queue: data.queue.into_iter()
+                .map(|o| {
+                    // Orders are taken off the queue when their count runs out.
+                    if o.count == Some(0) { return Err(Error::InvalidSave("factory order for none".into())) }
+                    Ok(Order { kind: loader.kind(o.kind)?, count: o.count })
+                })
+                .collect::<Result<_>>()?,
+            building: match data.building {
+                Some(k) => Some(loader.kind(k)?),
+                None => None,
+            },
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct Production;
 
+type ProductionData<'a> = (
+    ReadExpect<'a, Defs>,
+    WriteStorage<'a, Factory>,
+    WriteStorage<'a, resource::Sink>,
+    WriteStorage<'a, Progress>,
+    WriteStorage<'a, Power>,
+);
+
 impl<'a> System<'a> for Production {
-    type SystemData = (
-        WriteStorage<'a, Factory>,
-        WriteStorage<'a, resource::Sink>,
-        WriteStorage<'a, Progress>,
-        WriteStorage<'a, Power>,
-    );
+    type SystemData = ProductionData<'a>;
 
-    fn run(&mut self, (mut factories, mut sinks, mut progs, mut powers): Self::SystemData) {
+    fn run(&mut self, (defs, mut factories, mut sinks, mut progs, mut powers): Self::SystemData) {
         for (factory, sink, progress, power) in (&mut factories, &mut sinks, &mut progs, &mut powers).join() {
             // Check production state
             if progress.at().map_or(false, |p| p >= 1.0) {
@@ -250,13 +350,19 @@ impl<'a> System<'a> for Production {
                 factory.building = None;
             }
             
-            // Request the resources for the next queued item
-            let (cost, build_power, time) = {
-                let next = if let Some(f) = factory.queue.front() { f } else { continue };
-                next.cost()
-            };
+            // Request the resources for the next queued item, and nothing
+            // left over from an order that's since been moved or removed.
+            let next = factory.queue.front().map(|o| {
+                defs.kind(o.kind).cost.as_ref().expect("queued an unbuildable kind")
+            });
+            let stale: Vec<Resource> = sink.want.iter()
+                .filter(|&(res, c)| c > 0 && next.map_or(0, |cost| cost.resources.get(res)) == 0)
+                .map(|(res, _)| res)
+                .collect();
+            for res in stale { sink.want.set(res, 0); }
+            let cost = if let Some(c) = next { c } else { continue };
             let mut has_all = true;
-            for (res, count) in cost.iter() {
+            for (res, count) in cost.resources.iter() {
                 if sink.want.get(res) != count { sink.want.set(res, count); }
                 if sink.has.get(res) < count { has_all = false }
             }
@@ -264,16 +370,21 @@ impl<'a> System<'a> for Production {
                 continue;
             }
             // Start requesting power, and only continue if we're getting any.
-            power.set::<Self>(build_power);
+            power.set::<Self>(cost.power);
             if power.ratio() == 0.0 { continue }
             // Clear sink requests and start production.
-            for (res, count) in cost.iter() {
+            for (res, count) in cost.resources.iter() {
                 sink.want.set(res, 0);
                 sink.has.dec_by(res, count).unwrap();
             }
-            let to_build = factory.queue.pop_front().unwrap();
-            factory.building = Some(to_build);
-            progress.start(time, format!("{:?}", to_build));
+            let mut order = factory.queue.pop_front().unwrap();
+            factory.building = Some(order.kind);
+            progress.start(cost.time, defs.name(order.kind).to_string());
+            match order.count {
+                Some(1) => (),
+                Some(n) => { order.count = Some(n - 1); factory.queue.push_back(order); },
+                None => factory.queue.push_back(order),
+            }
         }
     }
 }
\ No newline at end of file
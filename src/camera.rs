@@ -0,0 +1,181 @@
+/*
+The camera picks which part of the map is on screen by setting ggez's screen
+coordinates, so everything drawn in map pixels lands in the right place and
+`graphics::get_screen_coordinates` is always the visible region.  Anything
+that works in window pixels - mouse events, imgui, the status text - has to
+go by `graphics::get_size` instead.
+
+When the view moves under a still mouse, whatever the mouse is over changes
+too; `update` hands back a motion to the last pointer position so it can be
+run through the modes again.
+*/
+
+use ggez::{
+    graphics::{self, Point2},
+    Context,
+};
+
+use crate::error::or_die;
+use crate::input::{Action, Input};
+
+/// Window pixels per second.
+const PAN_SPEED: f32 = 600.0;
+/// How close to the window edge the mouse has to be to scroll.
+const EDGE: i32 = 8;
+const ZOOM_STEP: f32 = 1.1;
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 4.0;
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    /// The map pixel at the middle of the window.
+    center: Point2,
+    /// Window pixels per map pixel.
+    zoom: f32,
+    /// Where the mouse was when a drag last moved the view.
+    drag: Option<(i32, i32)>,
+    pointer: Option<(i32, i32)>,
+    held: Vec<Action>,
+    moved: bool,
+}
+
+impl Camera {
+    pub fn new() -> Self {
+        Camera {
+            center: Point2::new(0.0, 0.0),
+            zoom: 1.0,
+            drag: None,
+            pointer: None,
+            held: vec![],
+            moved: true,
+        }
+    }
+
+    /// The part of the map that fits in a window of the given size.
+    pub fn view(&self, (width, height): (u32, u32)) -> graphics::Rect {
+        let w = (width as f32) / self.zoom;
+        let h = (height as f32) / self.zoom;
+        graphics::Rect { x: self.center.x - w / 2.0, y: self.center.y - h / 2.0, w, h }
+    }
+
+    pub fn apply(&self, ctx: &mut Context) {
+        let view = self.view(graphics::get_size(ctx));
+        or_die(|| Ok(graphics::set_screen_coordinates(ctx, view)?));
+    }
+
+    /// The map pixel under a window pixel.
+    fn to_map(&self, ctx: &Context, x: i32, y: i32) -> Point2 {
+        let (width, height) = graphics::get_size(ctx);
+        Point2::new(
+            self.center.x + ((x as f32) - (width as f32) / 2.0) / self.zoom,
+            self.center.y + ((y as f32) - (height as f32) / 2.0) / self.zoom,
+        )
+    }
+
+    fn pan(&mut self, dx: f32, dy: f32) {
+        if dx == 0.0 && dy == 0.0 { return }
+        self.center.x += dx / self.zoom;
+        self.center.y += dy / self.zoom;
+        self.moved = true;
+    }
+
+    /// Takes the input that moves the camera; true if it was used up.
+    pub fn handle_input(&mut self, ctx: &mut Context, input: Input) -> bool {
+        match input {
+            Input::Motion { x, y } => {
+                self.pointer = Some((x, y));
+                if let Some((from_x, from_y)) = self.drag {
+                    self.pan((from_x - x) as f32, (from_y - y) as f32);
+                    self.drag = Some((x, y));
+                    self.apply(ctx);
+                    return true
+                }
+                false
+            },
+            Input::Press { action: Action::Drag, x, y } => {
+                self.drag = Some((x, y));
+                true
+            },
+            Input::Release { action: Action::Drag, .. } => {
+                self.drag = None;
+                true
+            },
+            Input::Press { action: Action::ZoomIn, .. } => { self.zoom_by(ctx, 1); true },
+            Input::Press { action: Action::ZoomOut, .. } => { self.zoom_by(ctx, -1); true },
+            Input::Press { action, .. } if pan_dir(action).is_some() => {
+                if !self.held.contains(&action) { self.held.push(action); }
+                true
+            },
+            Input::Release { action, .. } if pan_dir(action).is_some() => {
+                self.held.retain(|&a| a != action);
+                true
+            },
+            Input::Resized => {
+                self.moved = true;
+                false
+            },
+            Input::Left => {
+                self.pointer = None;
+                self.drag = None;
+                false
+            },
+            _ => false,
+        }
+    }
+
+    fn zoom_by(&mut self, ctx: &mut Context, steps: i32) {
+        let (mx, my) = match self.pointer {
+            Some(p) => p,
+            None => {
+                let (width, height) = graphics::get_size(ctx);
+                ((width / 2) as i32, (height / 2) as i32)
+            },
+        };
+        // Zoom about the mouse, so the map under it stays put.
+        let before = self.to_map(ctx, mx, my);
+        let zoom = self.zoom * ZOOM_STEP.powi(steps);
+        self.zoom = zoom.max(MIN_ZOOM).min(MAX_ZOOM);
+        let after = self.to_map(ctx, mx, my);
+        self.center += before - after;
+        self.moved = true;
+        self.apply(ctx);
+    }
+
+    /// Pans for held keys and for the mouse resting at the window edge, then
+    /// applies the view.  If the view moved since the last update, returns
+    /// the last mouse motion to be handled again.
+    pub fn update(&mut self, ctx: &mut Context, dt: f32, edge_scroll: bool) -> Option<Input> {
+        let step = PAN_SPEED * dt;
+        let (mut dx, mut dy) = (0.0, 0.0);
+        for &action in &self.held {
+            if let Some((kx, ky)) = pan_dir(action) {
+                dx += kx * step;
+                dy += ky * step;
+            }
+        }
+        if edge_scroll && self.drag.is_none() {
+            if let Some((x, y)) = self.pointer {
+                let (width, height) = graphics::get_size(ctx);
+                let (width, height) = (width as i32, height as i32);
+                if x < EDGE { dx -= step } else if x >= width - EDGE { dx += step }
+                if y < EDGE { dy -= step } else if y >= height - EDGE { dy += step }
+            }
+        }
+        self.pan(dx, dy);
+        if !self.moved { return None }
+        self.moved = false;
+        self.apply(ctx);
+        self.pointer.map(|(x, y)| Input::Motion { x, y })
+    }
+}
+
+/// The direction an action pans the view in, if it does.
+fn pan_dir(action: Action) -> Option<(f32, f32)> {
+    match action {
+        Action::PanLeft => Some((-1.0, 0.0)),
+        Action::PanRight => Some((1.0, 0.0)),
+        Action::PanUp => Some((0.0, -1.0)),
+        Action::PanDown => Some((0.0, 1.0)),
+        _ => None,
+    }
+}
@@ -0,0 +1,604 @@
+/*
+Every player action that changes the simulation goes through `issue` as a
+`Command`, stamped with the tick it happened on.  That's what makes a session
+recordable: given the same starting world, replaying the commands at the same
+ticks reproduces it exactly.
+
+Entities are referred to by `Ref` rather than by `Entity`, since the replayed
+world allocates the same ids but the commands have to survive a trip through
+a file.  A command whose entities are gone, or lack what it acts on, does
+nothing.
+*/
+
+use std::{
+    collections::VecDeque,
+    fs,
+    path::Path,
+};
+
+use serde_derive::{Deserialize, Serialize};
+use specs::prelude::*;
+
+use crate::blueprint;
+use crate::build;
+use crate::defs::Defs;
+use crate::demolish;
+use crate::error::{Error, Result};
+use crate::geom;
+use crate::graph;
+use crate::power;
+use crate::reactor;
+use crate::resource::{self, Resource};
+use crate::save;
+
+pub const SCRIPT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Command {
+    QueueBuild { factory: Ref, kind: build::Kind },
+    UnqueueBuild { factory: Ref, kind: build::Kind },
+    InsertOrder { factory: Ref, index: usize, order: build::Order },
+    RemoveOrder { factory: Ref, index: usize },
+    MoveOrder { factory: Ref, from: usize, to: usize },
+    /// `None` repeats forever.
+    SetRepeat { factory: Ref, index: usize, count: Option<usize> },
+    /// Stops whatever the factory is in the middle of building, and gives back
+    /// what it cost.  There's no undoing it, since the refund may be spent or
+    /// spilled by then.
+    CancelBuilding { factory: Ref },
+    StartBuild { factory: Ref, kind: build::Kind, fork: Ref, at: save::Coord },
+    /// Takes back a `StartBuild` that made `node`.
+    CancelBuild { factory: Ref, kind: build::Kind, fork: Ref, at: save::Coord, node: Ref },
+    Deliver { from: Ref, to: Ref, kind: build::Kind },
+    PlaceBlueprint { blueprint: blueprint::Blueprint },
+    /// Takes away a placed blueprint's links, and whichever of its nodes
+    /// aren't finished yet.
+    ClearBlueprint { nodes: Vec<Ref>, links: Vec<Ref>, blueprint: blueprint::Blueprint },
+    MakeLink { from: Ref, to: Ref },
+    ToggleExclude { node: Ref, exclude: Ref },
+    SetTarget { node: Ref, resource: Resource, on: bool },
+    SetAccept { node: Ref, resource: Resource, on: bool },
+    SetPriority { node: Ref, priority: i32 },
+    SetPowerPriority { node: Ref, priority: power::Priority },
+    LinkPylons { from: Ref, to: Ref },
+    UnlinkPylons { from: Ref, to: Ref },
+    SetIsolated { node: Ref, isolated: bool },
+    DeleteNode { node: Ref },
+    DeleteLink { link: Ref },
+    Undo,
+    Redo,
+}
+
+/// An entity as commands name it.  The generation goes along with the id, so
+/// a command kept for undo can tell its entity is gone even once the id has
+/// been handed out again.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ref {
+    id: u32,
+    gen: i32,
+}
+
+impl Ref {
+    /// The entity, if it's still alive.
+    pub fn get(self, world: &World) -> Option<Entity> {
+        let ent = world.entities().entity(self.id);
+        if ent.gen().id() == self.gen && world.is_alive(ent) { Some(ent) } else { None }
+    }
+}
+
+impl From<Entity> for Ref {
+    fn from(ent: Entity) -> Self { Ref { id: ent.id(), gen: ent.gen().id() } }
+}
+
+/// What applying a command did.
+#[derive(Debug)]
+enum Outcome {
+    /// It took effect, and this command takes it back.
+    Applied(Command),
+    /// It took effect, and can't be taken back.
+    Irreversible,
+    /// It didn't take effect.  A command that no longer makes sense, like
+    /// redoing a build whose spot has since been taken, does nothing.
+    NoOp,
+}
+
+impl Command {
+    fn apply(&self, world: &mut World) -> Outcome {
+        self.try_apply(world).unwrap_or(Outcome::NoOp)
+    }
+    /// `None` is a no-op.
+    // One arm per command; splitting it up would just scatter them.
+    #[allow(clippy::cognitive_complexity)]
+    fn try_apply(&self, world: &mut World) -> Option<Outcome> {
+        use self::Command::*;
+        use self::Outcome::*;
+        Some(match *self {
+            // Definitions make sure whatever a factory can build has a cost.
+            QueueBuild { factory, kind } => {
+                let mut factories = world.write_storage::<build::Factory>();
+                let factory_c = factories.get_mut(factory.get(world)?)?;
+                if !factory_c.can_build().contains(&kind) { return None }
+                factory_c.queue_push(kind);
+                Applied(UnqueueBuild { factory, kind })
+            },
+            UnqueueBuild { factory, kind } => {
+                if !world.write_storage::<build::Factory>().get_mut(factory.get(world)?)?.unqueue(kind) {
+                    return None
+                }
+                Applied(QueueBuild { factory, kind })
+            },
+            // Resources already gathered for a removed order stay in the
+            // factory for whatever's queued next.
+            InsertOrder { factory, index, order } => {
+                let mut factories = world.write_storage::<build::Factory>();
+                let factory_c = factories.get_mut(factory.get(world)?)?;
+                if !factory_c.can_build().contains(&order.kind) || order.count == Some(0) { return None }
+                if !factory_c.insert_order(index, order) { return None }
+                Applied(RemoveOrder { factory, index })
+            },
+            RemoveOrder { factory, index } => {
+                let order = world.write_storage::<build::Factory>().get_mut(factory.get(world)?)?
+                    .remove_order(index)?;
+                Applied(InsertOrder { factory, index, order })
+            },
+            MoveOrder { factory, from, to } => {
+                if !world.write_storage::<build::Factory>().get_mut(factory.get(world)?)?.move_order(from, to) {
+                    return None
+                }
+                Applied(MoveOrder { factory, from: to, to: from })
+            },
+            SetRepeat { factory, index, count } => {
+                if count == Some(0) { return None }
+                let mut factories = world.write_storage::<build::Factory>();
+                let factory_c = factories.get_mut(factory.get(world)?)?;
+                let was = factory_c.queue().get(index)?.count;
+                factory_c.set_count(index, count);
+                Applied(SetRepeat { factory, index, count: was })
+            },
+            CancelBuilding { factory } => {
+                let factory_ent = factory.get(world)?;
+                let kind = world.write_storage::<build::Factory>().get_mut(factory_ent)?.cancel_building()?;
+                if let Some(p) = world.write_storage::<reactor::Progress>().get_mut(factory_ent) { p.clear(); }
+                if let Some(p) = world.write_storage::<power::Power>().get_mut(factory_ent) {
+                    p.clear::<build::Production>();
+                }
+                let at = world.read_storage::<graph::Node>().get(factory_ent)?.at();
+                let cost = world.read_resource::<Defs>().kind(kind).cost.as_ref()
+                    .expect("built an unbuildable kind").resources.clone();
+                // Whatever doesn't fit back in the factory spills.
+                let mut spill = vec![];
+                if let Some(sink) = world.write_storage::<resource::Sink>().get_mut(factory_ent) {
+                    for (res, count) in cost.iter() {
+                        if count == 0 { continue }
+                        if let Some(over) = sink.has.inc_by(res, count) { spill.push((res, over)); }
+                    }
+                }
+                for (res, count) in spill { reactor::make_waste(world, at, res, count); }
+                Irreversible
+            },
+            StartBuild { factory, kind, fork, at } => {
+                let factory_ent = factory.get(world)?;
+                let fork_ent = fork.get(world)?;
+                let coord = save::from_coord(at);
+                let fits = {
+                    let map = world.read_resource::<geom::Map>();
+                    let nodes = world.read_storage::<graph::Node>();
+                    nodes.get(fork_ent).map_or(false, |n| {
+                        graph::space_for_node(&map, coord) && graph::space_for_link(&map, n.at(), coord)
+                    })
+                };
+                let ready = fits && world.write_storage::<build::Factory>().get_mut(factory_ent)
+                    .map_or(false, |f| f.dec_built(kind).is_ok());
+                if !ready { return None }
+                let node = kind.start(world, factory_ent, fork_ent, coord);
+                Applied(CancelBuild { factory, kind, fork, at, node: node.into() })
+            },
+            CancelBuild { factory, kind, fork, at, node } => {
+                let node_ent = node.get(world)?;
+                world.read_storage::<graph::Node>().get(node_ent)?;
+                // A build still on its way is refunded as it's dropped.
+                let pending = world.read_storage::<build::Pending>().get(node_ent).is_some();
+                demolish::node(world, node_ent);
+                if let (false, Some(factory_ent)) = (pending, factory.get(world)) {
+                    if let Some(f) = world.write_storage::<build::Factory>().get_mut(factory_ent) {
+                        f.inc_built(kind);
+                    }
+                }
+                Applied(StartBuild { factory, kind, fork, at })
+            },
+            // There's no calling back a delivery once it's set off.
+            Deliver { from, to, kind } => {
+                let (from, to) = (from.get(world)?, to.get(world)?);
+                if !build::deliver(world, kind, from, to) { return None }
+                Irreversible
+            },
+            PlaceBlueprint { ref blueprint } => {
+                let (nodes, links) = blueprint.place(world)?;
+                Applied(ClearBlueprint {
+                    nodes: nodes.into_iter().map(Ref::from).collect(),
+                    links: links.into_iter().map(Ref::from).collect(),
+                    blueprint: blueprint.clone(),
+                })
+            },
+            ClearBlueprint { ref nodes, ref links, ref blueprint } => {
+                let nodes: Vec<Entity> = nodes.iter().filter_map(|n| n.get(world)).collect();
+                for node in nodes {
+                    if world.read_storage::<build::Pending>().get(node).is_some() {
+                        demolish::node(world, node);
+                    }
+                }
+                // Links to unfinished nodes went with them; this gets the rest,
+                // like those between nodes that were already there.
+                let links: Vec<Entity> = links.iter().filter_map(|l| l.get(world)).collect();
+                for link in links {
+                    demolish::link(world, link);
+                }
+                Applied(PlaceBlueprint { blueprint: blueprint.clone() })
+            },
+            MakeLink { from, to } => {
+                let (from, to) = (from.get(world)?, to.get(world)?);
+                if !graph::can_link(world, from, to) { return None }
+                let link = graph::make_link(world, from, to);
+                Applied(DeleteLink { link: link.into() })
+            },
+            ToggleExclude { node, exclude } => {
+                let exclude_ent = exclude.get(world)?;
+                let mut graphs = world.write_storage::<graph::AreaGraph>();
+                let excludes = graphs.get_mut(node.get(world)?)?.exclude_mut();
+                if !excludes.remove(&exclude_ent) { excludes.insert(exclude_ent); }
+                Applied(ToggleExclude { node, exclude })
+            },
+            SetTarget { node, resource, on } => {
+                let mut reactors = world.write_storage::<reactor::Reactor>();
+                let targets = reactors.get_mut(node.get(world)?)?.targets_mut();
+                let was = targets.contains(resource.index() as u32);
+                if on { targets.add(resource.index() as u32); } else { targets.remove(resource.index() as u32); }
+                Applied(SetTarget { node, resource, on: was })
+            },
+            SetAccept { node, resource, on } => {
+                let mut stores = world.write_storage::<resource::Storage>();
+                let store = stores.get_mut(node.get(world)?)?;
+                let was = store.accepts(resource);
+                store.set_accept(resource, on);
+                Applied(SetAccept { node, resource, on: was })
+            },
+            SetPriority { node, priority } => {
+                let mut sinks = world.write_storage::<resource::Sink>();
+                let sink = sinks.get_mut(node.get(world)?)?;
+                let was = sink.priority;
+                let priority = resource::clamp_priority(priority);
+                if priority == was { return Some(NoOp) }
+                sink.priority = priority;
+                Applied(SetPriority { node, priority: was })
+            },
+            SetPowerPriority { node, priority } => {
+                let mut powers = world.write_storage::<power::Power>();
+                let power = powers.get_mut(node.get(world)?)?;
+                let was = power.priority;
+                power.priority = priority;
+                Applied(SetPowerPriority { node, priority: was })
+            },
+            LinkPylons { from, to } => {
+                let (from_ent, to_ent) = (from.get(world)?, to.get(world)?);
+                if !power::can_link(world, from_ent, to_ent) { return None }
+                world.write_resource::<power::PowerGrid>().link(from_ent, to_ent);
+                Applied(UnlinkPylons { from, to })
+            },
+            UnlinkPylons { from, to } => {
+                let (from_ent, to_ent) = (from.get(world)?, to.get(world)?);
+                if !world.read_resource::<power::PowerGrid>().is_linked(from_ent, to_ent) { return None }
+                world.write_resource::<power::PowerGrid>().unlink(from_ent, to_ent, &world.read_storage());
+                Applied(LinkPylons { from, to })
+            },
+            SetIsolated { node, isolated } => {
+                let mut pylons = world.write_storage::<power::Pylon>();
+                let pylon = pylons.get_mut(node.get(world)?)?;
+                let was = pylon.isolated;
+                pylon.isolated = isolated;
+                Applied(SetIsolated { node, isolated: was })
+            },
+            // Demolished nodes take their contents with them, so there's no
+            // putting them back.
+            DeleteNode { node } => {
+                let node = node.get(world)?;
+                world.read_storage::<graph::Node>().get(node)?;
+                demolish::node(world, node);
+                Irreversible
+            },
+            DeleteLink { link } => {
+                let link = link.get(world)?;
+                let (from, to) = world.read_storage::<graph::Link>().get(link).map(|l| (l.from, l.to))?;
+                demolish::link(world, link);
+                Applied(MakeLink { from: from.into(), to: to.into() })
+            },
+            // Handled by `issue`.
+            Undo | Redo => return None,
+        })
+    }
+}
+
+/// What undoing and redoing will do, most recent last.  Each entry is the
+/// inverse of a command that was applied; applying it gives back the command
+/// for the other stack.  Anything that can't be undone clears both, since the
+/// entries behind it could refer to what it removed; a command that does
+/// nothing leaves them be.
+#[derive(Debug, Default)]
+pub struct History {
+    undo: Vec<Command>,
+    redo: Vec<Command>,
+}
+
+const HISTORY_LIMIT: usize = 100;
+
+impl History {
+    pub fn can_undo(&self) -> bool { !self.undo.is_empty() }
+    pub fn can_redo(&self) -> bool { !self.redo.is_empty() }
+    fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+    }
+    fn push_undo(&mut self, inverse: Command) {
+        self.undo.push(inverse);
+        if self.undo.len() > HISTORY_LIMIT { self.undo.remove(0); }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stamped {
+    pub tick: u64,
+    pub command: Command,
+}
+
+/// A recorded session: where it started from, and what the player did.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Script {
+    version: u32,
+    pub seed: u64,
+    pub load: Option<String>,
+    /// Commands name kinds and resources by index, so replaying needs the
+    /// same definitions.
+    kinds: Vec<String>,
+    resources: Vec<String>,
+    commands: Vec<Stamped>,
+}
+
+impl Script {
+    pub fn new(seed: u64, load: Option<String>, defs: &Defs) -> Self {
+        Script {
+            version: SCRIPT_VERSION, seed, load,
+            kinds: defs.names(), resources: defs.res_names(),
+            commands: vec![],
+        }
+    }
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let script: Script = ron::de::from_str(&fs::read_to_string(path)?)?;
+        if script.version != SCRIPT_VERSION {
+            return Err(Error::ScriptVersion(script.version))
+        }
+        Ok(script)
+    }
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, ron::ser::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+/// While this holds a script, issued commands are appended to it.
+#[derive(Debug, Default)]
+pub struct Recording(pub Option<Script>);
+
+impl Recording {
+    /// Stops recording, writing out the script if one was running.
+    pub fn finish<P: AsRef<Path>>(world: &mut World, path: P) -> Result<()> {
+        let script = world.write_resource::<Recording>().0.take();
+        if let Some(script) = script { script.write(path)?; }
+        Ok(())
+    }
+}
+
+/// Commands still to be replayed, in tick order.
+#[derive(Debug, Default)]
+pub struct Replay(VecDeque<Stamped>);
+
+impl Replay {
+    pub fn start(world: &mut World, script: Script) -> Result<()> {
+        let same = {
+            let defs = world.read_resource::<Defs>();
+            script.kinds == defs.names() && script.resources == defs.res_names()
+        };
+        if !same {
+            return Err(Error::Defs("script was recorded with different definitions".into()))
+        }
+        world.write_resource::<Replay>().0 = script.commands.into_iter().collect();
+        Ok(())
+    }
+}
+
+/// Apply a player command to the world, recording it if a recording is
+/// running.
+pub fn issue(world: &mut World, command: Command) {
+    match command {
+        // An undo or redo that no longer does anything is just dropped.
+        Command::Undo => {
+            let inverse = world.write_resource::<History>().undo.pop();
+            if let Some(inverse) = inverse {
+                let outcome = inverse.apply(world);
+                let mut history = world.write_resource::<History>();
+                match outcome {
+                    Outcome::Applied(c) => history.redo.push(c),
+                    Outcome::Irreversible => history.clear(),
+                    Outcome::NoOp => (),
+                }
+            }
+        },
+        Command::Redo => {
+            let redo = world.write_resource::<History>().redo.pop();
+            if let Some(redo) = redo {
+                let outcome = redo.apply(world);
+                let mut history = world.write_resource::<History>();
+                match outcome {
+                    Outcome::Applied(c) => history.push_undo(c),
+                    Outcome::Irreversible => history.clear(),
+                    Outcome::NoOp => (),
+                }
+            }
+        },
+        _ => {
+            let outcome = command.apply(world);
+            let mut history = world.write_resource::<History>();
+            match outcome {
+                Outcome::Applied(c) => {
+                    history.redo.clear();
+                    history.push_undo(c);
+                },
+                Outcome::Irreversible => history.clear(),
+                Outcome::NoOp => (),
+            }
+        },
+    }
+    let tick = world.read_resource::<super::Tick>().0;
+    if let Some(script) = &mut world.write_resource::<Recording>().0 {
+        script.commands.push(Stamped { tick, command });
+    }
+}
+
+/// Issue every replayed command that's due before the next tick runs.
+pub fn run_replay(world: &mut World) {
+    let tick = world.read_resource::<super::Tick>().0;
+    loop {
+        let next = {
+            let mut replay = world.write_resource::<Replay>();
+            let due = replay.0.front().map_or(false, |s| s.tick <= tick);
+            if due { replay.0.pop_front() } else { None }
+        };
+        match next {
+            Some(stamped) => issue(world, stamped.command),
+            None => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed(world: &World) -> Entity {
+        let nodes = world.read_storage::<graph::Node>();
+        (&*world.entities(), &nodes).join().next().unwrap().0
+    }
+
+    fn isolated(world: &World, node: Entity) -> bool {
+        world.read_storage::<power::Pylon>().get(node).unwrap().isolated
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut world = crate::test_world();
+        let seed = seed(&world);
+        issue(&mut world, Command::SetIsolated { node: seed.into(), isolated: true });
+        assert!(isolated(&world, seed));
+        issue(&mut world, Command::Undo);
+        assert!(!isolated(&world, seed));
+        issue(&mut world, Command::Redo);
+        assert!(isolated(&world, seed));
+        assert!(!world.read_resource::<History>().can_redo());
+    }
+
+    #[test]
+    fn priority_clamped() {
+        let mut world = crate::test_world();
+        let seed = seed(&world);
+        let priority = |world: &World| world.read_storage::<resource::Sink>().get(seed).unwrap().priority;
+        issue(&mut world, Command::SetPriority { node: seed.into(), priority: std::i32::MIN });
+        assert_eq!(priority(&world), resource::MIN_PRIORITY);
+        issue(&mut world, Command::SetPriority { node: seed.into(), priority: std::i32::MAX });
+        assert_eq!(priority(&world), resource::MAX_PRIORITY);
+        issue(&mut world, Command::Undo);
+        assert_eq!(priority(&world), resource::MIN_PRIORITY);
+    }
+
+    #[test]
+    fn no_op_keeps_history() {
+        let mut world = crate::test_world();
+        let seed = seed(&world);
+        issue(&mut world, Command::SetIsolated { node: seed.into(), isolated: true });
+        issue(&mut world, Command::Undo);
+        // Nothing to remove at that index.
+        issue(&mut world, Command::RemoveOrder { factory: seed.into(), index: 99 });
+        let history = world.read_resource::<History>();
+        assert!(history.can_redo());
+    }
+
+    #[test]
+    fn stale_ref_is_no_op() {
+        let mut world = crate::test_world();
+        let old = seed(&world);
+        issue(&mut world, Command::DeleteNode { node: old.into() });
+        world.maintain();
+        let new = graph::make_node(&mut world, hex2d::Coordinate { x: 0, y: 0 });
+        let start = world.read_resource::<Defs>().start();
+        start.make(&mut world, new);
+        assert_eq!(old.id(), new.id());
+        issue(&mut world, Command::SetIsolated { node: old.into(), isolated: true });
+        assert!(!isolated(&world, new));
+        issue(&mut world, Command::SetIsolated { node: new.into(), isolated: true });
+        assert!(isolated(&world, new));
+    }
+
+    #[test]
+    fn missing_component_is_no_op() {
+        let mut world = crate::test_world();
+        let seed = seed(&world);
+        let res = world.read_resource::<Defs>().resources().next().unwrap();
+        issue(&mut world, Command::SetTarget { node: seed.into(), resource: res, on: true });
+        assert!(!world.read_resource::<History>().can_undo());
+    }
+
+    fn kind(world: &World, name: &str) -> build::Kind {
+        world.read_resource::<Defs>().find(name).unwrap()
+    }
+
+    #[test]
+    fn queue_rejects_unbuildable() {
+        let mut world = crate::test_world();
+        let seed = seed(&world);
+        let own = kind(&world, "Seed");
+        issue(&mut world, Command::QueueBuild { factory: seed.into(), kind: own });
+        let order = build::Order { kind: own, count: Some(1) };
+        issue(&mut world, Command::InsertOrder { factory: seed.into(), index: 0, order });
+        let order = build::Order { kind: kind(&world, "Strut"), count: Some(0) };
+        issue(&mut world, Command::InsertOrder { factory: seed.into(), index: 0, order });
+        assert!(world.read_storage::<build::Factory>().get(seed).unwrap().queue().is_empty());
+        assert!(!world.read_resource::<History>().can_undo());
+    }
+
+    #[test]
+    fn cancel_building_refunds() {
+        let mut world = crate::test_world();
+        let mut update = crate::make_update();
+        let seed = seed(&world);
+        let strut = kind(&world, "Strut");
+        let carbon = world.read_resource::<Defs>().find_res("C").unwrap();
+        let has_carbon = |world: &World| world.read_storage::<resource::Sink>().get(seed).unwrap().has.get(carbon);
+        world.write_storage::<resource::Sink>().get_mut(seed).unwrap().has.set(carbon, 2);
+        issue(&mut world, Command::QueueBuild { factory: seed.into(), kind: strut });
+        for _ in 0..10 { crate::step(&mut world, &mut update); }
+        assert_eq!(world.read_storage::<build::Factory>().get(seed).unwrap().building(), Some(strut));
+        assert_eq!(has_carbon(&world), 0);
+
+        issue(&mut world, Command::CancelBuilding { factory: seed.into() });
+        assert_eq!(world.read_storage::<build::Factory>().get(seed).unwrap().building(), None);
+        assert_eq!(has_carbon(&world), 2);
+        // Undoing it would take back a refund that may be spent by then.
+        assert!(!world.read_resource::<History>().can_undo());
+    }
+
+    #[test]
+    fn irreversible_clears_history() {
+        let mut world = crate::test_world();
+        let seed = seed(&world);
+        issue(&mut world, Command::SetIsolated { node: seed.into(), isolated: true });
+        issue(&mut world, Command::DeleteNode { node: seed.into() });
+        assert!(!world.read_resource::<History>().can_undo());
+    }
+}
@@ -0,0 +1,559 @@
+/*
+Buildings and recipes are defined in a RON file read at startup, so the
+economy can be tuned without recompiling.  The file names things by string;
+`Defs::parse` resolves and checks every cross-reference up front, so a bad
+definition is reported once, clearly, rather than surfacing as a panic
+somewhere in the middle of a game.
+
+A `Kind` or `Resource` is an index into the loaded definitions.  Saves
+record the kind names they were made with and are mapped back through them on
+load.  Resources are stored inside every `Pool`, so rather than remap those a
+save only needs its resources to be a prefix of the current list: new
+resources can be added at the end without breaking old saves.
+*/
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    time::Duration,
+};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::resource::{self, Pool, Resource};
+use crate::util::f32_duration;
+
+pub const DEFS_PATH: &str = "defs.ron";
+
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Kind(usize);
+
+#[derive(Debug, Clone)]
+pub struct ResourceDef {
+    pub name: String,
+    /// RGB, each 0 to 1.
+    pub color: [f32; 3],
+}
+
+#[derive(Debug, Clone)]
+pub struct Recipe {
+    pub input: Pool,
+    pub output: Pool,
+    pub delay: Duration,
+    /// Total over the reaction, in kJ/mol; negative is consumed.
+    pub power: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct ReactorDef {
+    pub recipe: Recipe,
+    pub range: i32,
+    /// Power plants run whenever the grid wants power, rather than when
+    /// their output is wanted.
+    pub plant: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Cost {
+    pub resources: Pool,
+    pub power: f32,
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct FactoryDef {
+    pub range: i32,
+    pub builds: Vec<Kind>,
+    /// Already built and ready to place when the factory is made.
+    pub built: Vec<(Kind, usize)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StorageDef {
+    pub range: i32,
+    /// Resources not listed can't be stored.
+    pub caps: Vec<(Resource, usize)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BatteryDef {
+    /// kJ.
+    pub capacity: f32,
+    /// kJ/s, charging or discharging.
+    pub rate: f32,
+}
+
+/// How much power the pylon grid loses carrying it from supply to consumer,
+/// as a fraction of what's carried.
+#[derive(Debug, Clone, Default)]
+pub struct GridDef {
+    pub loss_per_hop: f32,
+    pub loss_per_hex: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct KindDef {
+    pub name: String,
+    pub link_range: i32,
+    /// `None` for kinds that can't be built, like the starting node.
+    pub cost: Option<Cost>,
+    pub reactor: Option<ReactorDef>,
+    pub factory: Option<FactoryDef>,
+    pub pylon: Option</* range= */ i32>,
+    pub storage: Option<StorageDef>,
+    pub battery: Option<BatteryDef>,
+    /// Flat power produced (or, if negative, drawn) just by existing.
+    pub power: Option<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Defs {
+    resources: Vec<ResourceDef>,
+    kinds: Vec<KindDef>,
+    start: Kind,
+    grid: GridDef,
+}
+
+impl Defs {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        Defs::parse(&text).map_err(|e| match e {
+            Error::Defs(msg) => Error::Defs(format!("{}: {}", path.display(), msg)),
+            e => e,
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Self> { read(text)?.resolve() }
+
+    /// As `parse`, with the kinds rotated left by `by`, the way a later
+    /// version of the file might have them.
+    #[cfg(test)]
+    pub fn parse_rotated(text: &str, by: usize) -> Result<Self> {
+        let mut file = read(text)?;
+        file.kinds.rotate_left(by);
+        file.resolve()
+    }
+
+    pub fn res(&self, res: Resource) -> &ResourceDef { &self.resources[res.index()] }
+    pub fn find_res(&self, name: &str) -> Option<Resource> {
+        self.resources.iter().position(|r| r.name == name).map(Resource::from_index)
+    }
+    pub fn resources(&self) -> impl Iterator<Item=Resource> {
+        (0..self.resources.len()).map(Resource::from_index)
+    }
+    pub fn res_names(&self) -> Vec<String> { self.resources.iter().map(|r| r.name.clone()).collect() }
+    /// Checks that resources numbered by an earlier `res_names()` mean the
+    /// same thing here.
+    pub fn check_res(&self, names: &[String]) -> Result<()> {
+        if names.len() > self.resources.len() {
+            return invalid(format!("{} resources expected, only {} defined", names.len(), self.resources.len()))
+        }
+        for (name, res) in names.iter().zip(&self.resources) {
+            if *name != res.name {
+                return invalid(format!("expected resource \"{}\", found \"{}\"", name, res.name))
+            }
+        }
+        Ok(())
+    }
+
+    pub fn kind(&self, kind: Kind) -> &KindDef { &self.kinds[kind.0] }
+    pub fn name(&self, kind: Kind) -> &str { &self.kinds[kind.0].name }
+    pub fn find(&self, name: &str) -> Option<Kind> {
+        self.kinds.iter().position(|k| k.name == name).map(Kind)
+    }
+    /// The kind of the node every new game starts with.
+    pub fn start(&self) -> Kind { self.start }
+    pub fn grid(&self) -> &GridDef { &self.grid }
+    pub fn names(&self) -> Vec<String> { self.kinds.iter().map(|k| k.name.clone()).collect() }
+    /// Maps kinds numbered by an earlier `names()` onto these definitions.
+    pub fn remap(&self, names: &[String]) -> Result<KindMap> {
+        let mut map = vec![];
+        for name in names {
+            map.push(self.find(name).ok_or_else(|| Error::Defs(format!("no kind named \"{}\"", name)))?);
+        }
+        Ok(KindMap(map))
+    }
+}
+
+#[derive(Debug)]
+pub struct KindMap(Vec<Kind>);
+
+impl KindMap {
+    pub fn get(&self, kind: Kind) -> Result<Kind> {
+        self.0.get(kind.0).cloned().ok_or_else(|| Error::Defs(format!("no kind numbered {}", kind.0)))
+    }
+}
+
+#[derive(Deserialize)]
+struct DefsFile {
+    start: String,
+    resources: Vec<ResourceFile>,
+    recipes: Vec<RecipeFile>,
+    kinds: Vec<KindFile>,
+    #[serde(default)]
+    grid: GridFile,
+}
+
+#[derive(Deserialize, Default)]
+struct GridFile {
+    loss_per_hop: f32,
+    loss_per_hex: f32,
+}
+
+#[derive(Deserialize)]
+struct ResourceFile {
+    name: String,
+    color: (f32, f32, f32),
+}
+
+#[derive(Deserialize)]
+struct RecipeFile {
+    name: String,
+    input: Vec<(String, usize)>,
+    output: Vec<(String, usize)>,
+    /// Seconds.
+    delay: f32,
+    power: f32,
+}
+
+#[derive(Deserialize)]
+struct KindFile {
+    name: String,
+    link_range: i32,
+    #[serde(default)]
+    cost: Option<CostFile>,
+    #[serde(default)]
+    reactor: Option<ReactorFile>,
+    #[serde(default)]
+    factory: Option<FactoryFile>,
+    #[serde(default)]
+    pylon: Option<PylonFile>,
+    #[serde(default)]
+    storage: Option<StorageFile>,
+    #[serde(default)]
+    battery: Option<BatteryFile>,
+    #[serde(default)]
+    power: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct CostFile {
+    resources: Vec<(String, usize)>,
+    power: f32,
+    /// Seconds.
+    time: f32,
+}
+
+#[derive(Deserialize)]
+struct ReactorFile {
+    recipe: String,
+    range: i32,
+    #[serde(default)]
+    plant: bool,
+}
+
+#[derive(Deserialize)]
+struct FactoryFile {
+    range: i32,
+    builds: Vec<String>,
+    #[serde(default)]
+    built: Vec<(String, usize)>,
+}
+
+#[derive(Deserialize)]
+struct PylonFile {
+    range: i32,
+}
+
+#[derive(Deserialize)]
+struct BatteryFile {
+    capacity: f32,
+    rate: f32,
+}
+
+#[derive(Deserialize)]
+struct StorageFile {
+    range: i32,
+    caps: Vec<(String, usize)>,
+}
+
+fn read(text: &str) -> Result<DefsFile> {
+    ron::de::from_str(text).map_err(|e| Error::Defs(e.to_string()))
+}
+
+fn invalid<T>(msg: String) -> Result<T> { Err(Error::Defs(msg)) }
+
+fn pool(what: &str, names: &HashMap<String, Resource>, counts: &[(String, usize)]) -> Result<Pool> {
+    let mut pool = Pool::new();
+    for (name, count) in counts {
+        let res = *names.get(name)
+            .ok_or_else(|| Error::Defs(format!("{}: no resource named \"{}\"", what, name)))?;
+        if pool.get(res) > 0 {
+            return invalid(format!("{}: {} listed twice", what, name))
+        }
+        if pool.set(res, *count).is_some() {
+            return invalid(format!("{}: {} {} is over the limit of {}", what, count, name, pool.cap(res)))
+        }
+    }
+    Ok(pool)
+}
+
+fn positive(what: &str, field: &str, value: f32) -> Result<()> {
+    if value > 0.0 { Ok(()) } else { invalid(format!("{}: {} must be positive", what, field)) }
+}
+
+fn fraction(what: &str, field: &str, value: f32) -> Result<f32> {
+    if value >= 0.0 && value < 1.0 { return Ok(value) }
+    invalid(format!("{}: {} must be at least 0 and below 1", what, field))
+}
+
+/// Building draws power, so a build cost can't make any.
+fn drawn(what: &str, field: &str, value: f32) -> Result<f32> {
+    if value <= 0.0 { Ok(value) } else { invalid(format!("{}: {} can't be positive", what, field)) }
+}
+
+fn cap(what: &str, name: &str, cap: usize) -> Result<usize> {
+    if cap == 0 {
+        return invalid(format!("{}: {} cap must be positive", what, name))
+    }
+    if cap > resource::MAX_CAP {
+        let limit = resource::MAX_CAP;
+        return invalid(format!("{}: {} cap of {} is over the limit of {}", what, name, cap, limit))
+    }
+    Ok(cap)
+}
+
+fn range(what: &str, range: i32) -> Result<i32> {
+    if range > 0 { Ok(range) } else { invalid(format!("{}: range must be positive", what)) }
+}
+
+impl DefsFile {
+    fn resolve(self) -> Result<Defs> {
+        let mut resources = vec![];
+        let mut res_names = HashMap::new();
+        for r in self.resources {
+            let (red, green, blue) = r.color;
+            if [red, green, blue].iter().any(|c| *c < 0.0 || *c > 1.0) {
+                return invalid(format!("resource \"{}\": colour components must be between 0 and 1", r.name))
+            }
+            let res = Resource::from_index(resources.len());
+            if res_names.insert(r.name.clone(), res).is_some() {
+                return invalid(format!("resource \"{}\" is defined twice", r.name))
+            }
+            resources.push(ResourceDef { name: r.name, color: [red, green, blue] });
+        }
+
+        let mut recipes = HashMap::new();
+        for r in self.recipes {
+            let what = format!("recipe \"{}\"", r.name);
+            positive(&what, "delay", r.delay)?;
+            let recipe = Recipe {
+                input: pool(&what, &res_names, &r.input)?,
+                output: pool(&what, &res_names, &r.output)?,
+                delay: f32_duration(r.delay),
+                power: r.power,
+            };
+            if recipes.insert(r.name.clone(), recipe).is_some() {
+                return invalid(format!("{} is defined twice", what))
+            }
+        }
+
+        let mut names = HashMap::new();
+        for (ix, k) in self.kinds.iter().enumerate() {
+            if names.insert(k.name.clone(), Kind(ix)).is_some() {
+                return invalid(format!("kind \"{}\" is defined twice", k.name))
+            }
+        }
+        let lookup = |what: &str, name: &str| -> Result<Kind> {
+            names.get(name).cloned()
+                .ok_or_else(|| Error::Defs(format!("{}: no kind named \"{}\"", what, name)))
+        };
+
+        let mut kinds = vec![];
+        for k in &self.kinds {
+            let what = format!("kind \"{}\"", k.name);
+            if k.link_range <= 0 {
+                return invalid(format!("{}: link_range must be positive", what))
+            }
+            let cost = match &k.cost {
+                None => None,
+                Some(c) => {
+                    positive(&what, "build time", c.time)?;
+                    Some(Cost {
+                        resources: pool(&what, &res_names, &c.resources)?,
+                        power: drawn(&what, "build power", c.power)?,
+                        time: f32_duration(c.time),
+                    })
+                },
+            };
+            let reactor = match &k.reactor {
+                None => None,
+                Some(r) => {
+                    let recipe = recipes.get(&r.recipe).cloned().ok_or_else(|| Error::Defs(
+                        format!("{}: no recipe named \"{}\"", what, r.recipe)))?;
+                    if r.plant && recipe.power <= 0.0 {
+                        return invalid(format!("{}: recipe \"{}\" doesn't make power", what, r.recipe))
+                    }
+                    Some(ReactorDef { recipe, range: range(&what, r.range)?, plant: r.plant })
+                },
+            };
+            let factory = match &k.factory {
+                None => None,
+                Some(f) => {
+                    let mut builds = vec![];
+                    for name in &f.builds {
+                        let kind = lookup(&what, name)?;
+                        if self.kinds[kind.0].cost.is_none() {
+                            return invalid(format!("{}: builds \"{}\", which has no cost", what, name))
+                        }
+                        builds.push(kind);
+                    }
+                    let mut built = vec![];
+                    for (name, count) in &f.built {
+                        built.push((lookup(&what, name)?, *count));
+                    }
+                    Some(FactoryDef { range: range(&what, f.range)?, builds, built })
+                },
+            };
+            let pylon = match &k.pylon {
+                None => None,
+                Some(p) => Some(range(&what, p.range)?),
+            };
+            let storage = match &k.storage {
+                None => None,
+                Some(st) => {
+                    let mut caps = vec![];
+                    for (name, count) in &st.caps {
+                        let res = *res_names.get(name).ok_or_else(|| Error::Defs(
+                            format!("{}: no resource named \"{}\"", what, name)))?;
+                        if caps.iter().any(|&(r, _)| r == res) {
+                            return invalid(format!("{}: {} listed twice", what, name))
+                        }
+                        caps.push((res, cap(&what, name, *count)?));
+                    }
+                    Some(StorageDef { range: range(&what, st.range)?, caps })
+                },
+            };
+            let battery = match &k.battery {
+                None => None,
+                Some(b) => {
+                    positive(&what, "battery capacity", b.capacity)?;
+                    positive(&what, "battery rate", b.rate)?;
+                    Some(BatteryDef { capacity: b.capacity, rate: b.rate })
+                },
+            };
+            kinds.push(KindDef {
+                name: k.name.clone(),
+                link_range: k.link_range,
+                cost, reactor, factory, pylon, storage, battery,
+                power: k.power,
+            });
+        }
+        let start = lookup("start", &self.start)?;
+        let grid = GridDef {
+            loss_per_hop: fraction("grid", "loss_per_hop", self.grid.loss_per_hop)?,
+            loss_per_hex: fraction("grid", "loss_per_hex", self.grid.loss_per_hex)?,
+        };
+        Ok(Defs { resources, kinds, start, grid })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stock() -> DefsFile { read(&fs::read_to_string(DEFS_PATH).unwrap()).unwrap() }
+
+    fn kind<'a>(file: &'a mut DefsFile, name: &str) -> &'a mut KindFile {
+        file.kinds.iter_mut().find(|k| k.name == name).unwrap()
+    }
+
+    /// The error from the stock definitions after `edit`.
+    fn broken<F: FnOnce(&mut DefsFile)>(edit: F) -> String {
+        let mut file = stock();
+        edit(&mut file);
+        match file.resolve() {
+            Err(Error::Defs(msg)) => msg,
+            other => panic!("expected a definitions error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn stock_defs_load() {
+        let defs = stock().resolve().unwrap();
+        assert_eq!(defs.name(defs.start()), "Seed");
+        assert!(defs.kind(defs.start()).factory.is_some());
+    }
+
+    #[test]
+    fn bad_references() {
+        assert_eq!(broken(|f| f.start = "Acorn".into()), "start: no kind named \"Acorn\"");
+        assert_eq!(
+            broken(|f| {
+                let carbon = f.recipes.iter_mut().find(|r| r.name == "Carbon").unwrap();
+                carbon.output = vec![("Coal".into(), 1)];
+            }),
+            "recipe \"Carbon\": no resource named \"Coal\"",
+        );
+        assert_eq!(
+            broken(|f| kind(f, "WaterSource").reactor.as_mut().unwrap().recipe = "Juice".into()),
+            "kind \"WaterSource\": no recipe named \"Juice\"",
+        );
+    }
+
+    #[test]
+    fn bad_values() {
+        assert_eq!(
+            broken(|f| f.grid.loss_per_hop = 1.5),
+            "grid: loss_per_hop must be at least 0 and below 1",
+        );
+        assert_eq!(
+            broken(|f| kind(f, "CarbonPlant").reactor.as_mut().unwrap().recipe = "Carbon".into()),
+            "kind \"CarbonPlant\": recipe \"Carbon\" doesn't make power",
+        );
+        assert_eq!(
+            broken(|f| kind(f, "Strut").link_range = 0),
+            "kind \"Strut\": link_range must be positive",
+        );
+        assert!(broken(|f| kind(f, "Factory").cost.as_mut().unwrap().resources = vec![("C".into(), 60)])
+            .contains("over the limit"));
+        assert_eq!(
+            broken(|f| kind(f, "Factory").cost.as_mut().unwrap().power = 50.0),
+            "kind \"Factory\": build power can't be positive",
+        );
+        let storage_caps = |f: &mut DefsFile, caps: Vec<(String, usize)>| {
+            kind(f, "Storage").storage.as_mut().unwrap().caps = caps;
+        };
+        assert_eq!(
+            broken(|f| storage_caps(f, vec![("C".into(), 0)])),
+            "kind \"Storage\": C cap must be positive",
+        );
+        assert!(broken(|f| storage_caps(f, vec![("C".into(), resource::MAX_CAP + 1)]))
+            .contains("over the limit"));
+        assert_eq!(
+            broken(|f| f.resources.iter_mut().find(|r| r.name == "O2").unwrap().name = "H2".into()),
+            "resource \"H2\" is defined twice",
+        );
+    }
+
+    #[test]
+    fn saved_names() {
+        let defs = stock().resolve().unwrap();
+        let mut res = defs.res_names();
+        res.pop();
+        assert!(defs.check_res(&res).is_ok());
+        res.swap(0, 1);
+        assert!(defs.check_res(&res).is_err());
+
+        let mut kinds = defs.names();
+        kinds.reverse();
+        let map = defs.remap(&kinds).unwrap();
+        assert_eq!(map.get(Kind(0)).unwrap(), defs.find(&kinds[0]).unwrap());
+        assert!(map.get(Kind(kinds.len())).is_err());
+        kinds.push("Acorn".into());
+        assert!(defs.remap(&kinds).is_err());
+    }
+}
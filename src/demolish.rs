@@ -0,0 +1,184 @@
+/*
+Demolition has to tear an element out of everything that refers to it, not
+just delete the entity.  Resources held by a demolished node, on their way to
+it, or waiting there for room on a link, are spilled as waste, as are packets
+caught on a demolished link.  A factory's stock and unfinished build spill as
+the resources they cost.  A node still waiting on a blueprint takes
+back the order the blueprint put in for it.
+Packets whose route crosses a demolished link further on re-plan when they
+reach their next node (see `graph::Reroute`).
+*/
+
+use specs::prelude::*;
+
+use crate::blueprint;
+use crate::build;
+use crate::defs::Defs;
+use crate::error::or_die;
+use crate::geom;
+use crate::graph;
+use crate::power;
+use crate::reactor;
+use crate::resource::{self, Resource};
+use crate::util::*;
+
+pub fn link(world: &mut World, link_ent: Entity) {
+    let on_link: Vec<Entity> = (&*world.entities(), &world.read_storage::<graph::FollowRoute>())
+        .join()
+        .filter(|(_, route)| route.current_link() == Some(link_ent))
+        .map(|(entity, _)| entity)
+        .collect();
+    for packet in on_link { drop_packet(world, packet); }
+    graph::delete_link(world, link_ent);
+}
+
+pub fn node(world: &mut World, node_ent: Entity) {
+    let at = or_die(|| Ok(try_get(&world.read_storage::<graph::Node>(), node_ent)?.at()));
+
+    // Anything headed here has nowhere to go.
+    let incoming: Vec<Entity> = {
+        let entities = world.entities();
+        let targets = world.read_storage::<resource::Target>();
+        let builds = world.read_storage::<build::Packet>();
+        let deliveries = world.read_storage::<build::Delivery>();
+        (&*entities, targets.maybe(), builds.maybe(), deliveries.maybe()).join()
+            .filter(|(_, target, build, delivery)| {
+                target.map_or(false, |t| t.node == node_ent)
+                    || build.map_or(false, |b| b.target() == node_ent)
+                    || delivery.map_or(false, |d| d.factory() == node_ent)
+            })
+            .map(|(entity, _, _, _)| entity)
+            .collect()
+    };
+    for packet in incoming { drop_in_flight(world, packet, Some(node_ent)); }
+    // Likewise anything waiting here, or just leaving.
+    let waiting: Vec<Entity> = (&*world.entities(), &world.read_storage::<graph::FollowRoute>())
+        .join()
+        .filter(|(_, route)| route.last_node() == node_ent)
+        .map(|(entity, _)| entity)
+        .collect();
+    for packet in waiting { drop_in_flight(world, packet, Some(node_ent)); }
+    for route in (&mut world.write_storage::<graph::FollowRoute>()).join() {
+        route.disown(node_ent);
+    }
+    // Nor is what a blueprint ordered for it.
+    let order = world.read_storage::<blueprint::Planned>().get(node_ent).and_then(|p| p.order());
+    if let Some((factory, kind)) = order {
+        if let Some(f) = world.write_storage::<build::Factory>().get_mut(factory) { f.unqueue(kind); }
+    }
+
+    let mut spill: Vec<(Resource, usize)> = vec![];
+    if let Some(source) = world.read_storage::<resource::Source>().get(node_ent) {
+        spill.extend(source.has.iter());
+    }
+    if let Some(sink) = world.read_storage::<resource::Sink>().get(node_ent) {
+        spill.extend(sink.has.iter());
+    }
+    // A factory's stock, and whatever it was in the middle of building, spill
+    // as what they cost.
+    if let Some(factory) = world.write_storage::<build::Factory>().get_mut(node_ent) {
+        let defs = world.read_resource::<Defs>();
+        let mut kinds: Vec<build::Kind> = factory.kinds().into_iter()
+            .flat_map(|k| std::iter::repeat(k).take(factory.built(k)))
+            .collect();
+        kinds.extend(factory.cancel_building());
+        for kind in kinds {
+            if let Some(cost) = &defs.kind(kind).cost { spill.extend(cost.resources.iter()); }
+        }
+    }
+    for (res, count) in spill {
+        if count > 0 { reactor::make_waste(world, at, res, count); }
+    }
+    for source in (&mut world.write_storage::<resource::Source>()).join() {
+        source.forget(node_ent);
+    }
+    world.write_resource::<power::PowerGrid>().remove(node_ent, &world.read_storage());
+
+    let links: Vec<Entity> = or_die(|| {
+        Ok(try_get(&world.read_storage::<graph::Node>(), node_ent)?.links().collect())
+    });
+    for link_ent in links {
+        // A dropped build packet can take other links down with its node.
+        if world.is_alive(link_ent) { link(world, link_ent); }
+    }
+    graph::delete_node(world, node_ent);
+}
+
+/// Removes a packet in flight.  A resource packet is spilled as waste; a build
+/// packet is refunded to its factory, and the node it was going to build is
+/// demolished.  A delivery goes back to the factory that sent it.
+pub fn drop_packet(world: &mut World, packet: Entity) {
+    drop_in_flight(world, packet, None)
+}
+
+fn drop_in_flight(world: &mut World, packet: Entity, demolishing: Option<Entity>) {
+    if !world.is_alive(packet) { return }
+    let at = world.read_storage::<geom::Motion>().get(packet).map(|m| m.coord());
+    let on = world.read_storage::<graph::FollowRoute>().get(packet).and_then(|r| r.current_link());
+    if let Some(l) = on {
+        if let Some(link) = world.write_storage::<graph::Link>().get_mut(l) { link.leave(); }
+    }
+    let resource = world.read_storage::<resource::Packet>().get(packet).map(|p| p.resource);
+    let target = world.read_storage::<resource::Target>().get(packet).map(|t| t.node);
+    if let (Some(res), Some(target)) = (resource, target) {
+        if let Some(sink) = world.write_storage::<resource::Sink>().get_mut(target) {
+            or_die(|| sink.in_transit.dec(res));
+        }
+        if let Some(at) = at { reactor::make_waste(world, at, res, 1); }
+    }
+    let build = world.read_storage::<build::Packet>().get(packet).map(|p| (p.kind(), p.target()));
+    let delivery = world.read_storage::<build::Delivery>().get(packet).map(|d| d.kind());
+    let factory = world.read_storage::<graph::FollowRoute>().get(packet).and_then(|r| r.graph());
+    or_die(|| Ok(world.delete_entity(packet)?));
+    let refund = build.map(|(kind, _)| kind).or(delivery);
+    if let (Some(kind), Some(f)) = (refund, factory) {
+        if let Some(factory) = world.write_storage::<build::Factory>().get_mut(f) {
+            factory.inc_built(kind);
+        }
+    }
+    if let Some((_, pending)) = build {
+        if Some(pending) != demolishing && world.is_alive(pending) { node(world, pending); }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex2d::Coordinate;
+
+    fn waste(world: &World, res: Resource) -> usize {
+        (&world.read_storage::<resource::Packet>(), &world.read_storage::<reactor::Waste>()).join()
+            .filter(|(p, _)| p.resource == res)
+            .count()
+    }
+
+    #[test]
+    fn factory_spills_stock_and_build() {
+        let mut world = crate::test_world();
+        let mut update = crate::make_update();
+        let factory = graph::make_node(&mut world, Coordinate { x: 5, y: 0 });
+        let start = world.read_resource::<Defs>().start();
+        start.make(&mut world, factory);
+        let (source, strut, carbon) = {
+            let defs = world.read_resource::<Defs>();
+            (defs.find("CarbonSource").unwrap(), defs.find("Strut").unwrap(), defs.find_res("C").unwrap())
+        };
+        world.write_storage::<build::Factory>().get_mut(factory).unwrap().queue_push(strut);
+        world.write_storage::<resource::Sink>().get_mut(factory).unwrap().has.set(carbon, 2);
+        for _ in 0..10 {
+            if world.read_storage::<build::Factory>().get(factory).unwrap().building().is_some() { break }
+            crate::step(&mut world, &mut update);
+        }
+        {
+            let factories = world.read_storage::<build::Factory>();
+            let f = factories.get(factory).unwrap();
+            assert_eq!((f.built(source), f.building()), (1, Some(strut)));
+        }
+        assert_eq!(waste(&world, carbon), 0);
+
+        node(&mut world, factory);
+        let defs = world.read_resource::<Defs>();
+        let cost = |kind| defs.kind(kind).cost.as_ref().unwrap().resources.get(carbon);
+        assert_eq!(waste(&world, carbon), cost(source) + cost(strut));
+    }
+}
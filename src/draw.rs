@@ -1,4 +1,7 @@
-use std::f32::consts::PI;
+use std::{
+    collections::BTreeMap,
+    f32::consts::PI,
+};
 
 use ggez::{
     self,
@@ -10,18 +13,22 @@ use ggez::{
     Context,
 };
 use hex2d::{Coordinate, Spacing, Spin, XY};
+use hibitset::BitSet;
 use specs::{
     prelude::*,
 };
 
+use crate::blueprint;
 use crate::build;
-use crate::error::or_die;
+use crate::defs::Defs;
+use crate::error::{Result, or_die};
 use crate::game;
 use crate::geom;
 use crate::graph;
 use crate::power;
 use crate::reactor;
 use crate::resource::{self, Resource};
+use crate::save::{self, Loader, Persist, Saver};
 use crate::util::{self, try_get};
 
 pub const HEX_SIDE: f32 = 10.0;
@@ -37,6 +44,20 @@ impl Component for Shape {
     type Storage = VecStorage<Self>;
 }
 
+impl Persist for Shape {
+    type Data = (Vec<save::Coord>, [f32; 4]);
+    fn save(&self, _: &Saver) -> Result<Self::Data> {
+        let Color { r, g, b, a } = self.color;
+        Ok((self.coords.iter().cloned().map(save::to_coord).collect(), [r, g, b, a]))
+    }
+    fn load((coords, [r, g, b, a]): Self::Data, _: &Loader) -> Result<Self> {
+        Ok(Shape {
+            coords: coords.into_iter().map(save::from_coord).collect(),
+            color: Color::new(r, g, b, a),
+        })
+    }
+}
+
 struct CellMesh(Mesh);
 
 struct Outlined {
@@ -79,10 +100,7 @@ impl ModeText {
 const PACKET_RADIUS: f32 = 4.0;
 
 pub fn build_sprites(world: &mut World, ctx: &mut Context) {
-    let points: Vec<Point2> = (0..6).map(|ix| {
-        let a = (PI / 3.0) * (ix as f32);
-        Point2::new(a.cos(), a.sin()) * HEX_SIDE
-    }).collect();
+    let points: Vec<Point2> = hex_corners().iter().map(|&c| Point2::new(0.0, 0.0) + c).collect();
     or_die(|| {
         world.add_resource(CellMesh(Mesh::new_polygon(ctx, DrawMode::Fill, &points)?));
         world.add_resource(OutlineSprite(Mesh::new_polygon(ctx, DrawMode::Line(2.0), &points)?));
@@ -112,16 +130,16 @@ pub fn draw(world: &mut World, ctx: &mut Context) {
     graphics::clear(ctx);
     graphics::set_background_color(ctx, graphics::Color::new(0.0, 0.0, 0.0, 1.0));
 
-    DrawShapes(ctx).run_now(&mut world.res);
-    DrawPackets(ctx).run_now(&mut world.res);
-    DrawBuildPackets(ctx).run_now(&mut world.res);
-    DrawSources(ctx).run_now(&mut world.res);
-    DrawSinks(ctx).run_now(&mut world.res);
-    DrawReactors(ctx).run_now(&mut world.res);
-    DrawPowerGrid(ctx).run_now(&mut world.res);
-    DrawSelectedAreas(ctx).run_now(&mut world.res);
-    DrawMouseWidget(ctx).run_now(&mut world.res);
-    DrawText(ctx).run_now(&mut world.res);
+    DrawShapes(ctx).run_now(&world.res);
+    DrawPackets(ctx).run_now(&world.res);
+    DrawBuildPackets(ctx).run_now(&world.res);
+    DrawSources(ctx).run_now(&world.res);
+    DrawSinks(ctx).run_now(&world.res);
+    DrawReactors(ctx).run_now(&world.res);
+    DrawPowerGrid(ctx).run_now(&world.res);
+    DrawSelectedAreas(ctx).run_now(&world.res);
+    DrawMouseWidget(ctx).run_now(&world.res);
+    DrawText(ctx).run_now(&world.res);
     world.maintain();
 
     //graphics::present(ctx);
@@ -138,42 +156,137 @@ impl ToPixelPoint for Coordinate {
     }
 }
 
+/// ggez can't tint instances of a mesh, so rather than a draw call per cell or
+/// packet, everything of one colour is gathered into a single mesh of
+/// triangles and drawn at once.
+#[derive(Default)]
+struct Batches(BTreeMap<(u8, u8, u8, u8), Vec<Point2>>);
+
+impl Batches {
+    fn add<I: IntoIterator<Item=Point2>>(&mut self, color: Color, triangles: I) {
+        self.0.entry(color.to_rgba()).or_insert_with(Vec::new).extend(triangles);
+    }
+    fn hex(&mut self, color: Color, center: Point2) {
+        let c = hex_corners();
+        self.add(color, [0, 1, 2, 0, 2, 3, 0, 3, 4, 0, 4, 5].iter().map(|&ix| center + c[ix]));
+    }
+    fn disc(&mut self, color: Color, center: Point2, radius: f32) {
+        const SIDES: usize = 8;
+        let corner = |ix: usize| {
+            let a = (2.0 * PI) * (ix as f32) / (SIDES as f32);
+            center + Vector2::new(a.cos(), a.sin()) * radius
+        };
+        self.add(color, (0..SIDES).flat_map(|ix| vec![center, corner(ix), corner(ix + 1)]));
+    }
+    fn draw(self, ctx: &mut Context) -> Result<()> {
+        for (rgba, triangles) in self.0 {
+            let mesh = Mesh::from_triangles(ctx, &triangles)?;
+            graphics::set_color(ctx, Color::from(rgba))?;
+            graphics::draw(ctx, &mesh, Point2::new(0.0, 0.0), 0.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// From a cell's centre.  The cell meshes and batched cells are all made
+/// from these, so they line up.
+fn hex_corners() -> [Vector2; 6] {
+    let mut out = [Vector2::new(0.0, 0.0); 6];
+    for (ix, corner) in out.iter_mut().enumerate() {
+        let a = (PI / 3.0) * (ix as f32);
+        *corner = Vector2::new(a.cos(), a.sin()) * HEX_SIDE;
+    }
+    out
+}
+
+/// The screen, grown to take in cells that are only partly on it.
+fn cull_rect(screen: graphics::Rect) -> graphics::Rect {
+    graphics::Rect {
+        x: screen.x - HEX_SIDE, y: screen.y - HEX_SIDE,
+        w: screen.w + 2.0 * HEX_SIDE, h: screen.h + 2.0 * HEX_SIDE,
+    }
+}
+
+/// Entities with a cell on screen.  Looking up every visible hex only pays
+/// when there are fewer of them than there are cells on the map; zoomed far
+/// out, `None` means check everything.
+fn visible(map: &geom::Map, screen: graphics::Rect) -> Option<BitSet> {
+    let center = Point2::new(screen.x + screen.w / 2.0, screen.y + screen.h / 2.0);
+    let half_diagonal = (screen.w * screen.w + screen.h * screen.h).sqrt() / 2.0;
+    // A hexagon of hexes of radius r reaches at least 1.5 * r sides out.
+    let radius = (half_diagonal / (1.5 * HEX_SIDE)).ceil() as i32 + 1;
+    if (3 * radius * (radius + 1)) as usize > map.len() { return None }
+    Some(map.in_range(Coordinate::from_pixel(center.x, center.y, SPACING), radius))
+}
+
 struct DrawShapes<'a>(&'a mut Context);
 
+type DrawShapesData<'a> = (
+    ReadExpect<'a, geom::Map>,
+    ReadExpect<'a, OutlineSprite>,
+    ReadStorage<'a, Shape>,
+    ReadStorage<'a, game::Selected>,
+    ReadStorage<'a, build::Pending>,
+    ReadStorage<'a, blueprint::Planned>,
+    ReadStorage<'a, graph::Link>,
+);
+
 impl<'a, 'b> System<'a> for DrawShapes<'b> {
-    type SystemData = (
-        ReadExpect<'a, CellMesh>,
-        ReadExpect<'a, OutlineSprite>,
-        ReadStorage<'a, Shape>,
-        ReadStorage<'a, game::Selected>,
-        ReadStorage<'a, build::Pending>,
-    );
+    type SystemData = DrawShapesData<'a>;
 
-    fn run(&mut self, (cell_mesh, outline, shapes, selected, pending): Self::SystemData) {
+    fn run(&mut self, (map, outline, shapes, selected, pending, planned, links): Self::SystemData) {
         let ctx = &mut self.0;
-        let screen = graphics::get_screen_coordinates(ctx);
+        let screen = cull_rect(graphics::get_screen_coordinates(ctx));
         let scale = (now_f32(ctx) * 3.0).sin() * 0.5 + 0.5;
         let sel_color = Color::new(scale, scale, scale, 1.0);
-        or_die(|| {
-            for (shape, opt_selected, opt_pending) in (&shapes, selected.maybe(), pending.maybe()).join() {
-                let mut color = shape.color;
-                if opt_pending.is_some() {
-                    color.a = 0.5;
-                }
-                graphics::set_color(ctx, color)?;
-                for coord in &shape.coords {
-                    let p = coord.to_pixel_point();
-                    if !screen.contains(p) { continue }
-                    graphics::draw(ctx, &cell_mesh.0, p, 0.0)?;
+        let mut batches = Batches::default();
+        let mut outlines = vec![];
+        let mut add = |
+            shape: &Shape, opt_selected: Option<&game::Selected>,
+            opt_pending: Option<&build::Pending>, opt_planned: Option<&blueprint::Planned>,
+            opt_link: Option<&graph::Link>,
+        | {
+            let mut color = shape.color;
+            if opt_planned.is_some() {
+                color.a = 0.25;
+            } else if opt_pending.is_some() {
+                color.a = 0.5;
+            }
+            // Links redden as they fill up, in steps so that they batch.
+            if let Some(link) = opt_link {
+                let full = (link.load() as f32) / (link.capacity() as f32);
+                let full = (full * 8.0).round() / 8.0;
+                color.r += (1.0 - color.r) * full;
+                color.g *= 1.0 - full;
+            }
+            for coord in &shape.coords {
+                let p = coord.to_pixel_point();
+                if !screen.contains(p) { continue }
+                batches.hex(color, p);
+                if opt_selected.is_some() { outlines.push(p); }
+            }
+        };
+        match visible(&map, screen) {
+            Some(on_screen) => {
+                let found = (
+                    &shapes, selected.maybe(), pending.maybe(), planned.maybe(), links.maybe(), &on_screen,
+                );
+                for (shape, opt_selected, opt_pending, opt_planned, opt_link, _) in found.join() {
+                    add(shape, opt_selected, opt_pending, opt_planned, opt_link);
                 }
-                if opt_selected.is_some() {
-                    graphics::set_color(ctx, sel_color)?;
-                    for coord in &shape.coords {
-                        let p = coord.to_pixel_point();
-                        if !screen.contains(p) { continue }
-                        graphics::draw(ctx, &outline.0, p, 0.0)?;
-                    }
+            },
+            None => {
+                let found = (&shapes, selected.maybe(), pending.maybe(), planned.maybe(), links.maybe());
+                for (shape, opt_selected, opt_pending, opt_planned, opt_link) in found.join() {
+                    add(shape, opt_selected, opt_pending, opt_planned, opt_link);
                 }
+            },
+        }
+        or_die(|| {
+            batches.draw(ctx)?;
+            graphics::set_color(ctx, sel_color)?;
+            for p in outlines {
+                graphics::draw(ctx, &outline.0, p, 0.0)?;
             }
             Ok(())
         })
@@ -182,44 +295,32 @@ impl<'a, 'b> System<'a> for DrawShapes<'b> {
 
 fn now_f32(ctx: &Context) -> f32 { util::duration_f32(get_time_since_start(ctx)) }
 
-fn res_color(res: Resource) -> Color {
-    match res {
-        Resource::H2 => Color::new(1.0, 1.0, 0.0, 1.0),
-        Resource::O2 => Color::new(0.0, 1.0, 0.0, 1.0),
-        Resource::H2O => Color::new(0.0, 0.0, 1.0, 1.0),
-        Resource::C => Color::new(0.7, 0.7, 0.7, 1.0),
-        Resource::CO2 => Color::new(1.0, 0.0, 1.0, 1.0),
-        Resource::CH4 => Color::new(1.0, 0.5, 0.0, 1.0),
-    }
+fn res_color(defs: &Defs, res: Resource) -> Color {
+    let [r, g, b] = defs.res(res).color;
+    Color::new(r, g, b, 1.0)
 }
 
+#[allow(clippy::too_many_arguments)]
 fn draw_orbit(
-    ctx: &mut Context, screen: graphics::Rect, sprite: &PacketSprite,
+    ctx: &mut Context, screen: graphics::Rect, sprite: &PacketSprite, defs: &Defs,
     orbit_radius: f32, orbit_speed: f32,
     coord: Coordinate, pool: &resource::Pool,
 ) {
-    let mut resources: Vec<(Resource, usize)> = vec![];
-    for res in Resource::all() {
-        let count = pool.get(res);
-        if count > 0 {
-            resources.push((res, count));
-        }
-    }
-    if resources.len() == 0 { return }
+    let resources: Vec<(Resource, usize)> = pool.iter().filter(|&(_, c)| c > 0).collect();
+    if resources.is_empty() { return }
 
     let orbit = (now_f32(ctx) * orbit_speed) % (2.0 * PI);
     let center_pt = coord.to_pixel_point();
     let inc = (2.0*PI) / (resources.len() as f32);
     or_die(|| {
-        for ix in 0..resources.len() {
+        for (ix, &(res, count)) in resources.iter().enumerate() {
             let cluster_pt = {
                 let angle = (ix as f32) * inc + orbit;
                 let v = Vector2::new(angle.cos(), angle.sin()) * orbit_radius;
                 center_pt + v
             };
-            let count = resources[ix].1;
             let cluster_inc = (2.0*PI) / (count as f32);
-            graphics::set_color(ctx, res_color(resources[ix].0))?;
+            graphics::set_color(ctx, res_color(defs, res))?;
             for px in 0..count {
                 let angle = (px as f32) * cluster_inc;
                 let v = Vector2::new(angle.cos(), angle.sin()) * PACKET_RADIUS * 1.5;
@@ -239,6 +340,7 @@ struct DrawSources<'a>(&'a mut Context);
 
 #[derive(shred_derive::SystemData)]
 struct DrawSourcesData<'a> {
+    defs: ReadExpect<'a, Defs>,
     packet_sprite: ReadExpect<'a, PacketSprite>,
     source_orbit: ReadExpect<'a, SourceOrbit>,
     nodes: ReadStorage<'a, graph::Node>,
@@ -261,7 +363,7 @@ impl<'a, 'b> System<'a> for DrawSources<'b> {
                 });
             }
             draw_orbit(
-                ctx, screen, &*data.packet_sprite,
+                ctx, screen, &*data.packet_sprite, &*data.defs,
                 /* radius= */ source_radius(), /* speed= */ 1.0,
                 node.at(), &source.has,
             );
@@ -271,16 +373,18 @@ impl<'a, 'b> System<'a> for DrawSources<'b> {
 
 struct DrawSelectedAreas<'a>(&'a mut Context);
 
+type DrawSelectedAreasData<'a> = (
+    Entities<'a>,
+    ReadExpect<'a, OutlineSprite>,
+    WriteStorage<'a, graph::AreaGraph>,
+    ReadStorage<'a, graph::Link>,
+    ReadStorage<'a, graph::Node>,
+    ReadStorage<'a, game::Selected>,
+    ReadStorage<'a, Shape>,
+);
+
 impl <'a, 'b> System<'a> for DrawSelectedAreas<'b> {
-    type SystemData = (
-        Entities<'a>,
-        ReadExpect<'a, OutlineSprite>,
-        WriteStorage<'a, graph::AreaGraph>,
-        ReadStorage<'a, graph::Link>,
-        ReadStorage<'a, graph::Node>,
-        ReadStorage<'a, game::Selected>,
-        ReadStorage<'a, Shape>,
-    );
+    type SystemData = DrawSelectedAreasData<'a>;
 
     fn run(&mut self, (entities, outline, mut graphs, links, nodes, selected, shapes): Self::SystemData) {
         let ctx = &mut self.0;
@@ -339,12 +443,13 @@ struct DrawSinks<'a>(&'a mut Context);
 
 impl<'a, 'b> System<'a> for DrawSinks<'b> {
     type SystemData = (
+        ReadExpect<'a, Defs>,
         ReadExpect<'a, PacketSprite>,
         ReadStorage<'a, graph::Node>,
         ReadStorage<'a, resource::Sink>,
     );
 
-    fn run(&mut self, (packet_sprite, nodes, sinks): Self::SystemData) {
+    fn run(&mut self, (defs, packet_sprite, nodes, sinks): Self::SystemData) {
         let ctx = &mut self.0;
         let screen = graphics::get_screen_coordinates(ctx);
         for (node, sink) in (&nodes, &sinks).join() {
@@ -374,7 +479,7 @@ impl<'a, 'b> System<'a> for DrawSinks<'b> {
                 });
             }
             draw_orbit(
-                ctx, screen, &*packet_sprite,
+                ctx, screen, &*packet_sprite, &*defs,
                 /* radius= */ 3.0f32.sqrt() * HEX_SIDE, /* speed= */ -0.5,
                 node.at(), &sink.has,
             );
@@ -421,7 +526,7 @@ impl<'a, 'b> System<'a> for DrawPowerGrid<'b> {
         let ctx = &mut self.0;
         let screen = graphics::get_screen_coordinates(ctx);
         or_die(|| {
-            graphics::set_color(ctx, Color::new(1.0, 0.0, 1.0, 0.5))?;
+            let grid_color = Color::new(1.0, 0.0, 1.0, 0.5);
             for (entity, node, opt_selected, pylon) in (&*entities, &nodes, selected.maybe(), &pylons).join() {
                 for other in grid.links(entity) {
                     let other_node = if let Some(n) = nodes.get(other) { n } else { continue };
@@ -429,9 +534,17 @@ impl<'a, 'b> System<'a> for DrawPowerGrid<'b> {
                     let to_pt = other_node.at().to_pixel_point();
                     // TODO: check if line crosses rather than endpoint is contained?
                     if !screen.contains(from_pt) && !screen.contains(to_pt) { continue }
+                    // Lines redden with the power lost getting this far;
+                    // fully red at half lost.
+                    let loss = grid.loss_at(entity).into_iter().chain(grid.loss_at(other))
+                        .fold(0.0, f32::max);
+                    let mut color = grid_color;
+                    color.b *= 1.0 - (loss * 2.0).min(1.0);
+                    graphics::set_color(ctx, color)?;
                     graphics::line(ctx, &[from_pt, to_pt], /* width= */ 1.0)?;
                 }
                 if opt_selected.is_some() {
+                    graphics::set_color(ctx, grid_color)?;
                     /*
                     for coord in node.at().ring(resource::PYLON_RANGE, Spin::CW(XY)) {
                         let p = coord.to_pixel_point();
@@ -460,33 +573,40 @@ const WASTE_SCALE: f32 = 0.5;
 
 impl<'a, 'b> System<'a> for DrawPackets<'b> {
     type SystemData = (
-        ReadExpect<'a, PacketSprite>,
+        ReadExpect<'a, Defs>,
         ReadStorage<'a, geom::Motion>,
         ReadStorage<'a, resource::Packet>,
         ReadStorage<'a, reactor::Waste>,
     );
 
-    fn run(&mut self, (packet_sprite, motions, packets, waste): Self::SystemData) {
+    fn run(&mut self, (defs, motions, packets, waste): Self::SystemData) {
         let ctx = &mut self.0;
-        let screen = graphics::get_screen_coordinates(ctx);
+        let screen = cull_rect(graphics::get_screen_coordinates(ctx));
+        // Outlines go underneath as slightly bigger white discs.
+        let mut outlines = Batches::default();
+        let mut fills = Batches::default();
+        let mut wasted = vec![];
         for (motion, packet, opt_waste) in (&motions, &packets, waste.maybe()).join() {
             let pos = motion.from + (motion.to - motion.from)*motion.at;
             if !screen.contains(pos) { continue }
-            or_die(|| {
-                graphics::set_color(ctx, res_color(packet.resource))?;
-                graphics::draw(ctx, &packet_sprite.0, pos, 0.0)?;
-                if opt_waste.is_some() {
-                    graphics::set_color(ctx, Color::new(1.0, 0.0, 0.0, 1.0))?;
-                    let up_l = pos + (Vector2::new(-HEX_SIDE, -HEX_SIDE) * WASTE_SCALE);
-                    let up_r = pos + (Vector2::new(HEX_SIDE, -HEX_SIDE) * WASTE_SCALE);
-                    let dn_l = pos + (Vector2::new(-HEX_SIDE, HEX_SIDE) * WASTE_SCALE);
-                    let dn_r = pos + (Vector2::new(HEX_SIDE, HEX_SIDE) * WASTE_SCALE);
-                    graphics::line(ctx, &[up_l, dn_r], 1.0)?;
-                    graphics::line(ctx, &[up_r, dn_l], 1.0)?;
-                }
-                Ok(())
-            });
+            outlines.disc(Color::new(1.0, 1.0, 1.0, 1.0), pos, PACKET_RADIUS + 0.25);
+            fills.disc(res_color(&defs, packet.resource), pos, PACKET_RADIUS - 0.25);
+            if opt_waste.is_some() { wasted.push(pos); }
         }
+        or_die(|| {
+            outlines.draw(ctx)?;
+            fills.draw(ctx)?;
+            graphics::set_color(ctx, Color::new(1.0, 0.0, 0.0, 1.0))?;
+            for pos in wasted {
+                let up_l = pos + (Vector2::new(-HEX_SIDE, -HEX_SIDE) * WASTE_SCALE);
+                let up_r = pos + (Vector2::new(HEX_SIDE, -HEX_SIDE) * WASTE_SCALE);
+                let dn_l = pos + (Vector2::new(-HEX_SIDE, HEX_SIDE) * WASTE_SCALE);
+                let dn_r = pos + (Vector2::new(HEX_SIDE, HEX_SIDE) * WASTE_SCALE);
+                graphics::line(ctx, &[up_l, dn_r], 1.0)?;
+                graphics::line(ctx, &[up_r, dn_l], 1.0)?;
+            }
+            Ok(())
+        });
     }
 }
 
@@ -497,33 +617,43 @@ impl<'a, 'b> System<'a> for DrawBuildPackets<'b> {
         ReadExpect<'a, BuildPacket>,
         ReadStorage<'a, geom::Motion>,
         ReadStorage<'a, build::Packet>,
+        ReadStorage<'a, build::Delivery>,
     );
 
-    fn run(&mut self, (sprite, motions, packets): Self::SystemData) {
+    fn run(&mut self, (sprite, motions, packets, deliveries): Self::SystemData) {
         let ctx = &mut self.0;
         let screen = graphics::get_screen_coordinates(ctx);
-        for (motion, _) in (&motions, packets.mask()).join() {
-            let pos = motion.from + (motion.to - motion.from)*motion.at;
-            if !screen.contains(pos) { continue }
-            or_die(|| {
-                graphics::set_color(ctx, Color::new(0.8, 0.8, 0.8, 1.0))?;
-                graphics::draw(ctx, &sprite.0, pos, 0.0)?;
-                Ok(())
-            });
+        // Deliveries look like builds, but gold.
+        let kinds = [
+            (packets.mask(), Color::new(0.8, 0.8, 0.8, 1.0)),
+            (deliveries.mask(), Color::new(0.9, 0.75, 0.2, 1.0)),
+        ];
+        for &(mask, color) in &kinds {
+            for (motion, _) in (&motions, mask).join() {
+                let pos = motion.from + (motion.to - motion.from)*motion.at;
+                if !screen.contains(pos) { continue }
+                or_die(|| {
+                    graphics::set_color(ctx, color)?;
+                    graphics::draw(ctx, &sprite.0, pos, 0.0)?;
+                    Ok(())
+                });
+            }
         }
     }
 }
 
 struct DrawMouseWidget<'a>(&'a mut Context);
 
+type DrawMouseWidgetData<'a> = (
+    ReadExpect<'a, OutlineSprite>,
+    ReadExpect<'a, CellMesh>,
+    ReadExpect<'a, game::MouseWidget>,
+    ReadExpect<'a, geom::Map>,
+    ReadStorage<'a, geom::Space>,
+);
+
 impl <'a, 'b> System<'a> for DrawMouseWidget<'b> {
-    type SystemData = (
-        ReadExpect<'a, OutlineSprite>,
-        ReadExpect<'a, CellMesh>,
-        ReadExpect<'a, game::MouseWidget>,
-        ReadExpect<'a, geom::Map>,
-        ReadStorage<'a, geom::Space>,
-    );
+    type SystemData = DrawMouseWidgetData<'a>;
 
     fn run(&mut self, (outline, cell, mw, map, spaces): Self::SystemData) {
         let ctx = &mut self.0;
@@ -536,43 +666,68 @@ impl <'a, 'b> System<'a> for DrawMouseWidget<'b> {
                     None => vec![coord],
                     Some(ent) => try_get(&spaces, ent)?.coords().iter().cloned().collect(),
                 };
-                let color = if mw.valid {
-                    Color::new(1.0, 1.0, 1.0, 1.0)
-                } else {
-                    Color::new(0.5, 0.0, 0.0, 1.0)
-                };
-                graphics::set_color(ctx, color)?;
-                for coord in coords {
-                    let (x, y) = coord.to_pixel(SPACING);
-                    graphics::draw(ctx, &outline.0, Point2::new(x, y), 0.0)?;
-                }
+                draw_outlines(ctx, &outline, &coords, mw.valid)?;
             },
             game::MWKind::PlaceNodeFrom(from_coord) => {
-                let color = if mw.valid {
-                    Color::new(0.8, 0.8, 0.8, 0.5)
-                } else {
-                    Color::new(0.8, 0.0, 0.0, 0.5)
-                };
-                graphics::set_color(ctx, color)?;
-                for c in graph::node_shape(coord) {
-                    let (x, y) = c.to_pixel(SPACING);
-                    graphics::draw(ctx, &cell.0, Point2::new(x, y), 0.0)?;
-                }
-                let color = if mw.valid {
-                    Color::new(0.0, 0.8, 0.0, 0.5)
-                } else {
-                    Color::new(0.8, 0.0, 0.0, 0.5)
-                };
-                graphics::set_color(ctx, color)?;
-                for c in graph::link_shape(from_coord, coord) {
-                    let (x, y) = c.to_pixel(SPACING);
-                    graphics::draw(ctx, &cell.0, Point2::new(x, y), 0.0)?;
+                draw_place_node(ctx, &cell, from_coord, coord, mw.valid)?;
+            },
+            game::MWKind::Blueprint { ref nodes, ref links, from } => {
+                let node_cells: Vec<Coordinate> = nodes.iter()
+                    .flat_map(|&at| graph::node_shape(at))
+                    .collect();
+                draw_cells(ctx, &cell, Color::new(0.8, 0.8, 0.8, 0.3), &node_cells)?;
+                let link_cells: Vec<Coordinate> = links.iter()
+                    .flat_map(|&(a, b)| graph::link_shape(a, b))
+                    .collect();
+                draw_cells(ctx, &cell, Color::new(0.0, 0.8, 0.0, 0.3), &link_cells)?;
+                match from {
+                    Some(from_coord) => draw_place_node(ctx, &cell, from_coord, coord, mw.valid)?,
+                    None => draw_outlines(ctx, &outline, &[coord], mw.valid)?,
                 }
             },
         }; Ok(()) })
     }
 }
 
+fn draw_outlines(ctx: &mut Context, outline: &OutlineSprite, coords: &[Coordinate], valid: bool) -> Result<()> {
+    let color = if valid {
+        Color::new(1.0, 1.0, 1.0, 1.0)
+    } else {
+        Color::new(0.5, 0.0, 0.0, 1.0)
+    };
+    graphics::set_color(ctx, color)?;
+    for coord in coords {
+        let (x, y) = coord.to_pixel(SPACING);
+        graphics::draw(ctx, &outline.0, Point2::new(x, y), 0.0)?;
+    }
+    Ok(())
+}
+
+fn draw_cells(ctx: &mut Context, cell: &CellMesh, color: Color, coords: &[Coordinate]) -> Result<()> {
+    graphics::set_color(ctx, color)?;
+    for c in coords {
+        let (x, y) = c.to_pixel(SPACING);
+        graphics::draw(ctx, &cell.0, Point2::new(x, y), 0.0)?;
+    }
+    Ok(())
+}
+
+/// A node at `coord` and its link back to `from`.
+fn draw_place_node(ctx: &mut Context, cell: &CellMesh, from: Coordinate, coord: Coordinate, valid: bool) -> Result<()> {
+    let color = if valid {
+        Color::new(0.8, 0.8, 0.8, 0.5)
+    } else {
+        Color::new(0.8, 0.0, 0.0, 0.5)
+    };
+    draw_cells(ctx, cell, color, &graph::node_shape(coord))?;
+    let color = if valid {
+        Color::new(0.0, 0.8, 0.0, 0.5)
+    } else {
+        Color::new(0.8, 0.0, 0.0, 0.5)
+    };
+    draw_cells(ctx, cell, color, &graph::link_shape(from, coord))
+}
+
 struct DrawText<'a>(&'a mut Context);
 
 impl<'a, 'b> System<'a> for DrawText<'b> {
@@ -585,11 +740,16 @@ impl<'a, 'b> System<'a> for DrawText<'b> {
     fn run(&mut self, (mode_text, paused_text, is_paused): Self::SystemData) {
         let ctx = &mut self.0;
         or_die(|| {
+            // Text stays put in the window, wherever the camera is.
+            let view = graphics::get_screen_coordinates(ctx);
+            let (w, h) = graphics::get_size(ctx);
+            graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, w as f32, h as f32))?;
             graphics::set_color(ctx, Color::new(0.5, 1.0, 0.5, 1.0))?;
             if is_paused.0 {
                 graphics::draw(ctx, &paused_text.0, Point2::new(0.0, 0.0), 0.0)?;
             }
-            graphics::draw(ctx, &mode_text.0, Point2::new(0.0, 780.0), 0.0)?;
+            graphics::draw(ctx, &mode_text.0, Point2::new(0.0, (h as f32) - 20.0), 0.0)?;
+            graphics::set_screen_coordinates(ctx, view)?;
             Ok(())
         });
     }
@@ -8,24 +8,30 @@ Those very few places where an operation can fail without leaving broken state
 get their own Error enum.
 */
 
-use std::sync::mpsc;
+use std::io;
 
 #[derive(Debug)]
 pub enum Error {
+    Args(String),
+    Defs(String),
+    InvalidSave(String),
     NoPath,
     NoSuchComponent,
     NoSuchEdge,
+    NoSuchEntity,
     Occupied,
     PathIxOverflow,
     PoolUnderflow,
-    PullChannel,
-    StorageOverflow,
-    #[allow(unused)]  // TODO
-    WrongEdge,
+    SaveVersion(u32),
+    ScriptVersion(u32),
+    Settings(String),
+    UnknownPowerUser(String),
     Ggez(ggez::GameError),
     Specs(specs::error::Error),
     SpecsGen(specs::error::WrongGeneration),
-    Channel(mpsc::SendError<bool>),
+    Io(io::Error),
+    RonSer(ron::ser::Error),
+    RonDe(ron::de::Error),
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -47,7 +53,14 @@ impl From<specs::error::Error> for Error {
 impl From<specs::error::WrongGeneration> for Error {
     fn from(err: specs::error::WrongGeneration) -> Self { Error::SpecsGen(err) }
 }
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self { Error::Io(err) }
+}
+
+impl From<ron::ser::Error> for Error {
+    fn from(err: ron::ser::Error) -> Self { Error::RonSer(err) }
+}
 
-impl From<mpsc::SendError<bool>> for Error {
-    fn from(err: mpsc::SendError<bool>) -> Self { Error::Channel(err) }
-}
\ No newline at end of file
+impl From<ron::de::Error> for Error {
+    fn from(err: ron::de::Error) -> Self { Error::RonDe(err) }
+}
@@ -1,5 +1,4 @@
 use ggez::{
-    event::{Event, Keycode},
     graphics,
     Context,
 };
@@ -10,18 +9,24 @@ use specs::{
     storage::BTreeStorage,
 };
 
+use crate::blueprint;
 use crate::build;
+use crate::camera;
+use crate::command::{self, Command};
+use crate::defs::Defs;
 use crate::draw;
-use crate::error::or_die;
+use crate::error::{Result, or_die};
 use crate::geom;
 use crate::graph;
+use crate::input::{Action, Input};
 use crate::mode::{Mode, EventAction, TopAction};
 use crate::power;
 use crate::reactor;
 use crate::resource::{self, Resource};
-use crate::util::*;
+use crate::save::{self, Loader, Persist, Saver};
 
 pub fn prep_world(world: &mut World) {
+    world.add_resource(camera::Camera::new());
     world.add_resource(MouseWidget {
         coord: None,
         kind: MWKind::None,
@@ -29,13 +34,21 @@ pub fn prep_world(world: &mut World) {
     });
 }
 
-pub struct Play;
+pub struct Play {
+    /// The window width the Play window was last placed for.
+    placed_for: f32,
+}
 
 impl Play {
-    fn window<F: FnOnce(&mut World)>(&self, world: &mut World, ui: &Ui, f: F) -> Option<EventAction> {
+    pub fn new() -> Self { Play { placed_for: 0.0 } }
+    fn window<F: FnOnce(&mut World)>(&mut self, world: &mut World, ui: &Ui, f: F) -> Option<EventAction> {
+        // Keep to the top right, unless moved, until the window is resized.
+        let width = ui.frame_size().logical_size.0 as f32;
+        let cond = if (width - self.placed_for).abs() > 0.5 { ImGuiCond::Always } else { ImGuiCond::FirstUseEver };
+        self.placed_for = width;
         ui.window(im_str!("Play"))
             .always_auto_resize(true)
-            .position((600.0, 100.0), ImGuiCond::FirstUseEver)
+            .position((width - 200.0, 100.0), cond)
             .build(|| {
             {
                 let p = &mut *world.write_resource::<super::Paused>();
@@ -43,12 +56,30 @@ impl Play {
                     if ui.small_button(im_str!("Unpause")) {
                         p.0 = false;
                     }
-                } else {
-                    if ui.small_button(im_str!("Pause")) {
-                        p.0 = true;
-                    }
+                } else if ui.small_button(im_str!("Pause")) {
+                    p.0 = true;
                 }
             }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Save")) {
+                world.write_resource::<save::Requested>().0 = Some(save::Request::Save);
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Load")) {
+                world.write_resource::<save::Requested>().0 = Some(save::Request::Load);
+            }
+            let (can_undo, can_redo) = {
+                let history = world.read_resource::<command::History>();
+                (history.can_undo(), history.can_redo())
+            };
+            if can_undo {
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Undo")) { command::issue(world, Command::Undo); }
+            }
+            if can_redo {
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Redo")) { command::issue(world, Command::Redo); }
+            }
             f(world);
         });
         None
@@ -63,22 +94,24 @@ impl Mode for Play {
     fn on_hide(&mut self, world: &mut World) {
         world.write_resource::<MouseWidget>().kind = MWKind::None;
     }
-    fn on_event(&mut self, world: &mut World, ctx: &mut Context, event: Event) -> EventAction {
-        match event {
-            Event::MouseMotion { x, y, .. } => {
+    fn on_event(&mut self, world: &mut World, ctx: &mut Context, input: Input) -> EventAction {
+        match input {
+            Input::Motion { x, y } => {
                 let coord = pixel_to_coord(ctx, x, y);
                 world.write_resource::<MouseWidget>().coord = Some(coord);
             },
-            Event::KeyDown { keycode: Some(Keycode::P), .. } => {
+            Input::Press { action: Action::Pause, .. } => {
                 let p = &mut *world.write_resource::<super::Paused>();
                 p.0 = !p.0;
             },
+            Input::Press { action: Action::Undo, .. } => command::issue(world, Command::Undo),
+            Input::Press { action: Action::Redo, .. } => command::issue(world, Command::Redo),
             _ => (),
         }
         EventAction::Done
     }
-    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, event: Event) -> TopAction {
-        if let Some(ent) = handle_node_selection(world, ctx, &event) {
+    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, input: Input) -> TopAction {
+        if let Some(ent) = handle_node_selection(world, ctx, input) {
             TopAction::push(NodeSelected(ent))
         } else {
             TopAction::AsEvent
@@ -103,8 +136,8 @@ impl NodeSelected {
     fn window<F: FnOnce(&mut World)>(&self, world: &mut World, ui: &Ui, f: F) {
         ui.window(im_str!("Node")).always_auto_resize(true).build(|| {
             let mut kinds: Vec<String> = vec![];
-            if world.read_storage::<reactor::Reactor>().get(self.0).is_some() {
-                kinds.push("Reactor".into());
+            if let Some(r) = world.read_storage::<reactor::Reactor>().get(self.0) {
+                kinds.push(if r.is_plant() { "Power Plant" } else { "Reactor" }.into());
             }
             if world.read_storage::<power::Pylon>().get(self.0).is_some() {
                 kinds.push("Pylon".into());
@@ -112,9 +145,15 @@ impl NodeSelected {
             if world.read_storage::<power::Power>().get(self.0).is_some() {
                 kinds.push("Power".into());
             }
+            if world.read_storage::<power::Battery>().get(self.0).is_some() {
+                kinds.push("Battery".into());
+            }
             if world.read_storage::<build::Factory>().get(self.0).is_some() {
                 kinds.push("Factory".into());
             }
+            if world.read_storage::<resource::Storage>().get(self.0).is_some() {
+                kinds.push("Storage".into());
+            }
             if kinds.is_empty() {
                 kinds = vec!["None".into()];
             }
@@ -130,9 +169,25 @@ impl NodeSelected {
                     let dir = if total >= 0.0 { "Output" } else { "Input" };
                     ui.text(format!(
                         "Power {}: {:.0}% ({:+}/s of {:+}/s)", dir,
-                        100.0*power.ratio(), power.from_grid(), power.total()));
+                        100.0*power.ratio(), power.grid(), power.total()));
+                }
+                if power.lost() > 0.0 {
+                    let carried = power.grid().abs() + power.lost();
+                    ui.text(format!(
+                        "Transmission Loss: {:.1}% ({:.0}/s)",
+                        100.0*power.lost()/carried, power.lost()));
                 }
             }
+            if let Some(battery) = world.read_storage::<power::Battery>().get(self.0) {
+                let state = if battery.flow() > 0.0 { "discharging" }
+                    else if battery.flow() < 0.0 { "charging" }
+                    else { "idle" };
+                ui.text(format!(
+                    "Battery: {:.0}/{:.0} ({:.0}%), {} {:.0}/s",
+                    battery.charge(), battery.capacity(),
+                    100.0*battery.charge()/battery.capacity(),
+                    state, battery.flow().abs()));
+            }
             if let Some(prog) = world.read_storage::<reactor::Progress>().get(self.0) {
                 if let Some((p, l)) = prog.at_label() {
                     ui.text(format!("Progress ({}): {:.0}%", l, 100.0*p));
@@ -144,7 +199,6 @@ impl NodeSelected {
 }
 
 /*
-const REACTION_TIME: Duration = Duration::from_millis(5000);
 const FACTORY_RANGE: i32 = 20;
 */
 
@@ -165,149 +219,242 @@ impl Mode for NodeSelected {
     fn on_hide(&mut self, world: &mut World) {
         world.write_resource::<MouseWidget>().kind = MWKind::None;
     }
-    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, event: Event) -> TopAction {
+    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, input: Input) -> TopAction {
         let mut click = false;
-        match event {
-            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => return TopAction::Pop,
-            Event::MouseButtonDown { .. } => click = true,
+        match input {
+            Input::Press { action: Action::Cancel, .. } => return TopAction::Pop,
+            Input::Press { action: Action::Select, .. } => click = true,
+            Input::Press { action: Action::AddLink, .. } => return TopAction::push(PlaceLink(self.0)),
+            Input::Press { action: Action::RemoveLink, .. } => return TopAction::push(RemoveLink(self.0)),
+            Input::Press { action: Action::Demolish, .. } => {
+                command::issue(world, Command::DeleteNode { node: self.0.into() });
+                return TopAction::Pop
+            },
+            Input::Press { action: Action::Build, .. } => {
+                // Place whichever kind the factory has ready first.
+                let ready = world.read_storage::<build::Factory>().get(self.0).and_then(|f| {
+                    f.kinds().into_iter().find(|&k| f.built(k) > 0)
+                });
+                if let Some(kind) = ready {
+                    return TopAction::push(BuildFrom { source: self.0, kind })
+                }
+            },
             _ => (),
         };
-        if let Some(ent) = handle_node_selection(world, ctx, &event) {
+        if let Some(ent) = handle_node_selection(world, ctx, input) {
             TopAction::swap(NodeSelected(ent))
+        } else if click {
+            TopAction::Pop
         } else {
-            if click {
-                TopAction::Pop
-            } else {
-                TopAction::AsEvent
-            }
+            TopAction::AsEvent
         }
     }
     fn on_ui(&mut self, world: &mut World, ui: &Ui) -> EventAction {
         self.window(world, ui, |_| {});
         EventAction::Continue
     }
+    // The node window's widgets, built in one closure.
+    #[allow(clippy::cognitive_complexity)]
     fn on_top_ui(&mut self, world: &mut World, ui: &Ui) -> TopAction {
         // AsEvent causes on_ui to get called, which causes the non-top widgets to be
         // double-added.
         let mut action = TopAction::continue_();
         self.window(world, ui, |world| {
-            /*
-            if self.is_plain(world) {
-                use resource::Pool;
-                if ui.small_button(im_str!("Make Reactor")) {
-                    ui.open_popup(im_str!("Make Reactor"));
-                }
-                ui.popup(im_str!("Make Reactor"), || {
-                    // Power is in kJ/mol
-                    if ui.menu_item(im_str!("2H2O -> O2 + 2H2")).build() {
-                        self.add_reactor(world,
-                            /* input= */ Pool::from(vec![(Resource::H2O, 2)]),
-                            /* delay= */ REACTION_TIME,
-                            /* output= */ Pool::from(vec![(Resource::O2, 1), (Resource::H2, 2)]),
-                            /* total_power= */ -3242.0,
-                        );
-                    }
-                    if ui.menu_item(im_str!("C + O2 => CO2")).build() {
-                        self.add_reactor(world,
-                            /* input= */ Pool::from(vec![(Resource::C, 1), (Resource::O2, 1)]),
-                            /* delay= */ REACTION_TIME,
-                            /* output= */ Pool::from(vec![(Resource::CO2, 1)]),
-                            /* total_power= */ 396.0,
-                        );
-                    }
-                    if ui.menu_item(im_str!("CO2 + 4H2 => CH4 + 2H2O")).build() {
-                        self.add_reactor(world,
-                            /* input= */ Pool::from(vec![(Resource::CO2, 1), (Resource::H2, 4)]),
-                            /* delay= */ REACTION_TIME,
-                            /* output= */ Pool::from(vec![(Resource::CH4, 1), (Resource::H2O, 2)]),
-                            /* total_power= */ 165.0,
-                        );
-                    }
-                    if ui.menu_item(im_str!("CH4 + 2O2 => CO2 + 2H2O")).build() {
-                        self.add_reactor(world,
-                            /* input= */ Pool::from(vec![(Resource::CH4, 1), (Resource::O2, 2)]),
-                            /* delay= */ REACTION_TIME,
-                            /* output= */ Pool::from(vec![(Resource::CO2, 1), (Resource::H2O, 2)]),
-                            /* total_power= */ 891.0,
-                        );
-                    }
-                });
-                if ui.small_button(im_str!("Make H2O Storage")) {
-                    let mut pool = Pool::new();
-                    for res in Resource::all() {
-                        pool.set_cap(res, 0);
-                    }
-                    pool.set_cap(Resource::H2O, 6);
-                    resource::Source::add(world, self.0, pool, /* range= */ 20);
-                    or_die(|| {
-                        world.write_storage().insert(self.0, resource::Sink::new())?;
-                        world.write_storage().insert(self.0, resource::Storage)?;
-                        Ok(())
-                    });
-                }
-            */
             ui.separator();
             if ui.small_button(im_str!("Add Link")) {
                 action = TopAction::push(PlaceLink(self.0));
             }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Remove Link")) {
+                action = TopAction::push(RemoveLink(self.0));
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Blueprint")) {
+                action = TopAction::push(PlanBlueprint::new(world, self.0));
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Demolish")) {
+                command::issue(world, Command::DeleteNode { node: self.0.into() });
+                action = TopAction::Pop;
+                return
+            }
             if world.read_storage::<graph::AreaGraph>().get(self.0).is_some() {
                 ui.separator();
                 if ui.small_button(im_str!("Toggle Exclude")) {
                     action = TopAction::push(ToggleExclude(self.0));
                 }
             }
-            if let Some(factory) = world.write_storage::<build::Factory>().get_mut(self.0) {
+            let mut commands = vec![];
+            if let Some(pylon) = world.read_storage::<power::Pylon>().get(self.0) {
+                ui.separator();
+                if ui.small_button(im_str!("Edit Grid Links")) {
+                    action = TopAction::push(EditGridLinks(self.0));
+                }
+                ui.same_line(0.0);
+                let mut isolated = pylon.isolated;
+                if ui.checkbox(im_str!("Isolated"), &mut isolated) {
+                    commands.push(Command::SetIsolated { node: self.0.into(), isolated });
+                }
+            }
+            if let Some(power) = world.read_storage::<power::Power>().get(self.0) {
+                ui.separator();
+                ui.text("Power Priority:");
+                for &priority in power::Priority::all().iter() {
+                    ui.same_line(0.0);
+                    let label = if priority == power.priority { format!("[{:?}]", priority) }
+                        else { format!("{:?}", priority) };
+                    if ui.small_button(&ImString::new(label)) && priority != power.priority {
+                        commands.push(Command::SetPowerPriority { node: self.0.into(), priority });
+                    }
+                }
+            }
+            if let Some(factory) = world.read_storage::<build::Factory>().get(self.0) {
                 ui.separator();
-                let mut kinds: Vec<build::Kind> = factory.can_build().iter().cloned().collect();
-                kinds.sort();
-                for kind in kinds {
-                    let name = format!("{:?}", kind);
+                let defs = world.read_resource::<Defs>();
+                for kind in factory.kinds() {
+                    let name = defs.name(kind).to_string();
                     ui.text(&name);
                     ui.same_line(100.0);
                     let built = factory.built(kind);
                     ui.text(format!("{}", built));
                     ui.same_line(115.0);
                     ui.push_id(&name);
-                    if ui.small_button(im_str!("+")) {
-                        factory.queue_push(kind);
+                    if factory.can_build().contains(&kind) {
+                        if ui.small_button(im_str!("+")) {
+                            commands.push(Command::QueueBuild { factory: self.0.into(), kind });
+                        }
+                        ui.same_line(0.0);
                     }
                     if built > 0 {
-                        ui.same_line(0.0);
                         if ui.small_button(im_str!("->")) {
                             action = TopAction::push(BuildFrom { source: self.0, kind });
                         }
+                        ui.same_line(0.0);
+                        if ui.small_button(im_str!("=>")) {
+                            action = TopAction::push(DeliverFrom { source: self.0, kind });
+                        }
                     }
                     ui.pop_id();
                 }
+                let factory_id = self.0.into();
+                if let Some(kind) = factory.building() {
+                    ui.separator();
+                    ui.text(format!("Building: {}", defs.name(kind)));
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("Cancel")) {
+                        commands.push(Command::CancelBuilding { factory: factory_id });
+                    }
+                }
                 let queue = factory.queue();
                 if !queue.is_empty() {
                     ui.separator();
-                    for &kind in queue {
-                        ui.text(format!("{:?}", kind));
+                    for (index, order) in queue.iter().enumerate() {
+                        ui.text(defs.name(order.kind));
+                        ui.same_line(100.0);
+                        match order.count {
+                            Some(n) => ui.text(format!("x{}", n)),
+                            None => ui.text("x*"),
+                        }
+                        ui.same_line(130.0);
+                        ui.push_id(index as i32);
+                        if index > 0 {
+                            if ui.small_button(im_str!("^")) {
+                                commands.push(Command::MoveOrder { factory: factory_id, from: index, to: index - 1 });
+                            }
+                            ui.same_line(0.0);
+                        }
+                        if index + 1 < queue.len() {
+                            if ui.small_button(im_str!("v")) {
+                                commands.push(Command::MoveOrder { factory: factory_id, from: index, to: index + 1 });
+                            }
+                            ui.same_line(0.0);
+                        }
+                        if let Some(n) = order.count {
+                            if n > 1 {
+                                if ui.small_button(im_str!("-")) {
+                                    commands.push(Command::SetRepeat { factory: factory_id, index, count: Some(n - 1) });
+                                }
+                                ui.same_line(0.0);
+                            }
+                            if ui.small_button(im_str!("+")) {
+                                commands.push(Command::SetRepeat { factory: factory_id, index, count: Some(n + 1) });
+                            }
+                            ui.same_line(0.0);
+                        }
+                        let mut forever = order.count.is_none();
+                        if ui.checkbox(im_str!("Forever"), &mut forever) {
+                            let count = if forever { None } else { Some(1) };
+                            commands.push(Command::SetRepeat { factory: factory_id, index, count });
+                        }
+                        ui.same_line(0.0);
+                        if ui.small_button(im_str!("x")) {
+                            commands.push(Command::RemoveOrder { factory: factory_id, index });
+                        }
+                        ui.pop_id();
                     }
                 }
             }
-            if let Some(r) = world.write_storage::<reactor::Reactor>().get_mut(self.0) {
+            if let Some(r) = world.read_storage::<reactor::Reactor>().get(self.0) {
                 ui.separator();
+                let defs = world.read_resource::<Defs>();
                 let mut parts = vec![];
                 parts.push(
-                    if r.input().is_empty() { "*".into() } else { r.input().str() }
+                    if r.input().is_empty() { "*".into() } else { r.input().str(&defs) }
                 );
                 parts.push("->".into());
                 parts.push(
-                    if r.output().is_empty() { "*".into() } else { r.output().str() }
+                    if r.output().is_empty() { "*".into() } else { r.output().str(&defs) }
                 );
                 ui.text(parts.join(" "));
                 ui.text("Build Targets:");
-                let output: Vec<_> = r.output().iter().collect();
-                let targets = r.targets_mut();
-                for (r, c) in output {
+                let targets = r.targets();
+                for (res, c) in r.output().iter() {
                     if c == 0 { continue }
-                    let mut has = targets.contains(r as u32);
-                    ui.checkbox(&ImString::new(format!("{:?}", r)), &mut has);
-                    if has { targets.add(r as u32); } else { targets.remove(r as u32); }
+                    let had = targets.contains(res.index() as u32);
+                    let mut has = had;
+                    ui.checkbox(&ImString::new(defs.res(res).name.clone()), &mut has);
+                    if has != had {
+                        commands.push(Command::SetTarget { node: self.0.into(), resource: res, on: has });
+                    }
+                }
+            }
+            {
+                let stores = world.read_storage::<resource::Storage>();
+                let sources = world.read_storage::<resource::Source>();
+                let sinks = world.read_storage::<resource::Sink>();
+                if let (Some(store), Some(source), Some(sink)) =
+                    (stores.get(self.0), sources.get(self.0), sinks.get(self.0)) {
+                    ui.separator();
+                    let defs = world.read_resource::<Defs>();
+                    ui.text("Accept:");
+                    for res in defs.resources() {
+                        let cap = source.has.cap(res);
+                        if cap == 0 { continue }
+                        let had = store.accepts(res);
+                        let mut has = had;
+                        ui.checkbox(&ImString::new(defs.res(res).name.clone()), &mut has);
+                        ui.same_line(100.0);
+                        ui.text(format!("{}/{}", source.has.get(res), cap));
+                        if has != had {
+                            commands.push(Command::SetAccept { node: self.0.into(), resource: res, on: has });
+                        }
+                    }
+                    ui.text(format!("Priority: {}", sink.priority));
+                    ui.same_line(0.0);
+                    ui.push_id("priority");
+                    if ui.small_button(im_str!("-")) && sink.priority > resource::MIN_PRIORITY {
+                        commands.push(Command::SetPriority { node: self.0.into(), priority: sink.priority - 1 });
+                    }
+                    ui.same_line(0.0);
+                    if ui.small_button(im_str!("+")) && sink.priority < resource::MAX_PRIORITY {
+                        commands.push(Command::SetPriority { node: self.0.into(), priority: sink.priority + 1 });
+                    }
+                    ui.pop_id();
                 }
             }
+            for cmd in commands {
+                command::issue(world, cmd);
+            }
         });
         action
     }
@@ -326,9 +473,9 @@ impl Mode for BuildFrom {
     fn on_hide(&mut self, world: &mut World) {
         world.write_resource::<MouseWidget>().kind = MWKind::None;
     }
-    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, event: Event) -> TopAction {
-        match event {
-            Event::MouseButtonDown { x, y, .. } => {
+    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, input: Input) -> TopAction {
+        match input {
+            Input::Press { action: Action::Select, x, y } => {
                 let coord = pixel_to_coord(ctx, x, y);
                 let found = world.read_resource::<geom::Map>().get(coord);
                 match found {
@@ -350,7 +497,7 @@ impl Mode for BuildFrom {
                     _ => TopAction::AsEvent,
                 }
             },
-            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => TopAction::Pop,
+            Input::Press { action: Action::Cancel, .. } => TopAction::Pop,
             _ => TopAction::AsEvent,
         }
     }
@@ -388,27 +535,27 @@ impl Mode for BuildTo {
     fn on_hide(&mut self, world: &mut World) {
         world.write_resource::<MouseWidget>().kind = MWKind::None;
     }
-    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, event: Event) -> TopAction {
-        match event {
-            Event::MouseMotion { x, y, .. } => {
+    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, input: Input) -> TopAction {
+        match input {
+            Input::Motion { x, y } => {
                 let coord = pixel_to_coord(ctx, x, y);
                 world.write_resource::<MouseWidget>().valid = self.valid_to(world, coord);
                 TopAction::AsEvent
             },
-            Event::MouseButtonDown { x, y, .. } => {
+            Input::Press { action: Action::Select, x, y } => {
                 let coord = pixel_to_coord(ctx, x, y);
                 if !self.valid_to(world, coord) {
                     return TopAction::Do(EventAction::Done)
                 }
-                or_die(|| {
-                    try_get_mut(&mut world.write_storage::<build::Factory>(), self.source)?
-                        .dec_built(self.kind)?;
-                    Ok(())
+                command::issue(world, Command::StartBuild {
+                    factory: self.source.into(),
+                    kind: self.kind,
+                    fork: self.fork.into(),
+                    at: save::to_coord(coord),
                 });
-                self.kind.start(world, self.source, self.fork, coord);
                 TopAction::Pop
             },
-            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => TopAction::swap(BuildFrom {
+            Input::Press { action: Action::Cancel, .. } => TopAction::swap(BuildFrom {
                 source: self.source, kind: self.kind,
             }),
             _ => TopAction::AsEvent,
@@ -416,6 +563,226 @@ impl Mode for BuildTo {
     }
 }
 
+/// Picks another factory to send a built item to.
+struct DeliverFrom {
+    source: Entity,
+    kind: build::Kind,
+}
+
+impl DeliverFrom {
+    fn valid_to(&self, world: &World, target: Option<Entity>) -> bool {
+        let target = if let Some(t) = target { t } else { return false };
+        if target == self.source || world.read_storage::<build::Factory>().get(target).is_none() {
+            return false
+        }
+        world.write_storage::<graph::AreaGraph>().get_mut(self.source).map_or(false, |ag| {
+            let (mut nodes, _) = ag.nodes_route();
+            nodes.any(|n| n == target)
+        })
+    }
+}
+
+impl Mode for DeliverFrom {
+    fn name(&self) -> &str { "deliver to" }
+    fn on_show(&mut self, world: &mut World) {
+        world.write_resource::<MouseWidget>().kind = MWKind::Highlight;
+    }
+    fn on_hide(&mut self, world: &mut World) {
+        world.write_resource::<MouseWidget>().kind = MWKind::None;
+    }
+    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, input: Input) -> TopAction {
+        match input {
+            Input::Motion { x, y } => {
+                let coord = pixel_to_coord(ctx, x, y);
+                let found = world.read_resource::<geom::Map>().get(coord);
+                world.write_resource::<MouseWidget>().valid = self.valid_to(world, found);
+                TopAction::AsEvent
+            },
+            Input::Press { action: Action::Select, x, y } => {
+                let coord = pixel_to_coord(ctx, x, y);
+                let found = world.read_resource::<geom::Map>().get(coord);
+                if !self.valid_to(world, found) {
+                    return TopAction::Do(EventAction::Done)
+                }
+                command::issue(world, Command::Deliver {
+                    from: self.source.into(),
+                    to: found.unwrap().into(),
+                    kind: self.kind,
+                });
+                TopAction::Pop
+            },
+            Input::Press { action: Action::Cancel, .. } => TopAction::Pop,
+            _ => TopAction::AsEvent,
+        }
+    }
+}
+
+/// Lays out nodes and links from `start` to be built together.  Clicking
+/// empty space plans a node linked to the anchor, and makes it the anchor;
+/// clicking a node, planned or not, moves the anchor there, or with another
+/// tool links or routes to it.
+struct PlanBlueprint {
+    plan: blueprint::Blueprint,
+    start: Entity,
+    anchor: blueprint::End,
+    kind: Option<build::Kind>,
+    tool: Tool,
+    /// Why the last route couldn't be planned.
+    note: Option<&'static str>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Tool {
+    Nodes,
+    Links,
+    /// A chain of the chosen kind, around whatever's in the way.
+    Route,
+}
+
+impl PlanBlueprint {
+    fn new(world: &World, start: Entity) -> Self {
+        PlanBlueprint {
+            plan: blueprint::Blueprint::default(),
+            start,
+            anchor: blueprint::End::Node(start.into()),
+            kind: Self::kinds(world).first().cloned(),
+            tool: Tool::Nodes,
+            note: None,
+        }
+    }
+
+    /// Anything some factory can build.
+    fn kinds(world: &World) -> Vec<build::Kind> {
+        let mut kinds: Vec<build::Kind> = world.read_storage::<build::Factory>().join()
+            .flat_map(|f| f.can_build().iter().cloned().collect::<Vec<_>>())
+            .collect();
+        kinds.sort();
+        kinds.dedup();
+        kinds
+    }
+
+    fn show(&self, world: &World) {
+        let at = |end| self.plan.end(world, end).map(|(at, _)| at);
+        let nodes = self.plan.nodes.iter().map(|&(_, at)| save::from_coord(at)).collect();
+        let links = self.plan.links.iter()
+            .filter_map(|&(a, b)| Some((at(a)?, at(b)?)))
+            .collect();
+        let from = if self.tool == Tool::Nodes { at(self.anchor) } else { None };
+        world.write_resource::<MouseWidget>().kind = MWKind::Blueprint { nodes, links, from };
+    }
+
+    fn end_at(&self, world: &World, coord: Coordinate) -> Option<blueprint::End> {
+        match world.read_resource::<geom::Map>().get(coord) {
+            Some(ent) if world.read_storage::<graph::Node>().get(ent).is_some() => {
+                Some(blueprint::End::Node(ent.into()))
+            },
+            Some(_) => None,
+            None => self.plan.planned_at(coord),
+        }
+    }
+
+    fn valid_at(&self, world: &World, coord: Coordinate) -> bool {
+        match (self.end_at(world, coord), self.kind) {
+            (Some(end), _) => match self.tool {
+                Tool::Nodes => true,
+                Tool::Links => self.plan.can_add_link(world, self.anchor, end),
+                // Too slow to plan on every move; try it on the click.
+                Tool::Route => end != self.anchor,
+            },
+            (None, Some(kind)) => {
+                self.tool == Tool::Nodes && self.plan.can_add_node(world, kind, coord, self.anchor)
+            },
+            (None, None) => false,
+        }
+    }
+}
+
+impl Mode for PlanBlueprint {
+    fn name(&self) -> &str { "blueprint" }
+    fn on_show(&mut self, world: &mut World) { self.show(world); }
+    fn on_hide(&mut self, world: &mut World) {
+        world.write_resource::<MouseWidget>().kind = MWKind::None;
+    }
+    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, input: Input) -> TopAction {
+        match input {
+            Input::Motion { x, y } => {
+                let coord = pixel_to_coord(ctx, x, y);
+                world.write_resource::<MouseWidget>().valid = self.valid_at(world, coord);
+                TopAction::AsEvent
+            },
+            Input::Press { action: Action::Select, x, y } => {
+                let coord = pixel_to_coord(ctx, x, y);
+                if !self.valid_at(world, coord) { return TopAction::done() }
+                match (self.end_at(world, coord), self.kind) {
+                    (Some(end), kind) => match self.tool {
+                        Tool::Nodes => self.anchor = end,
+                        Tool::Links => self.plan.add_link(self.anchor, end),
+                        Tool::Route => {
+                            let routed = kind.map_or(false, |k| self.plan.add_route(world, k, self.anchor, end));
+                            if routed {
+                                self.anchor = end;
+                                self.note = None;
+                            } else {
+                                self.note = Some("No route found");
+                            }
+                        },
+                    },
+                    (None, Some(kind)) => self.anchor = self.plan.add_node(kind, coord, self.anchor),
+                    (None, None) => (),
+                }
+                self.show(world);
+                TopAction::done()
+            },
+            Input::Press { action: Action::Cancel, .. } => TopAction::Pop,
+            _ => TopAction::AsEvent,
+        }
+    }
+    fn on_top_ui(&mut self, world: &mut World, ui: &Ui) -> TopAction {
+        let kinds: Vec<(build::Kind, String)> = {
+            let defs = world.read_resource::<Defs>();
+            Self::kinds(world).into_iter().map(|k| (k, defs.name(k).to_string())).collect()
+        };
+        let mut changed = false;
+        let mut place = false;
+        ui.window(im_str!("Blueprint")).always_auto_resize(true).build(|| {
+            for (kind, name) in kinds {
+                let label = if Some(kind) == self.kind { format!("[{}]", name) } else { name };
+                if ui.small_button(&ImString::new(label)) && Some(kind) != self.kind {
+                    self.kind = Some(kind);
+                    changed = true;
+                }
+            }
+            ui.separator();
+            ui.text("Add:");
+            for &(tool, name) in [(Tool::Nodes, "Nodes"), (Tool::Links, "Links"), (Tool::Route, "Route")].iter() {
+                ui.same_line(0.0);
+                let label = if tool == self.tool { format!("[{}]", name) } else { name.to_string() };
+                if ui.small_button(&ImString::new(label)) && tool != self.tool {
+                    self.tool = tool;
+                    changed = true;
+                }
+            }
+            if let Some(note) = self.note { ui.text(note); }
+            ui.text(format!("{} nodes, {} links planned", self.plan.nodes.len(), self.plan.links.len()));
+            if !self.plan.is_empty() {
+                if ui.small_button(im_str!("Place")) { place = true; }
+                ui.same_line(0.0);
+                if ui.small_button(im_str!("Clear")) {
+                    self.plan = blueprint::Blueprint::default();
+                    self.anchor = blueprint::End::Node(self.start.into());
+                    changed = true;
+                }
+            }
+        });
+        if place {
+            command::issue(world, Command::PlaceBlueprint { blueprint: self.plan.clone() });
+            return TopAction::Pop
+        }
+        if changed { self.show(world); }
+        TopAction::continue_()
+    }
+}
+
 struct PlaceLink(Entity);
 
 impl Mode for PlaceLink {
@@ -426,9 +793,9 @@ impl Mode for PlaceLink {
     fn on_hide(&mut self, world: &mut World) {
         world.write_resource::<MouseWidget>().kind = MWKind::None;
     }
-    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, event: Event) -> TopAction {
-        match event {
-            Event::MouseButtonDown { x, y, .. } => {
+    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, input: Input) -> TopAction {
+        match input {
+            Input::Press { action: Action::Select, x, y } => {
                 let coord = pixel_to_coord(ctx, x, y);
                 let found = world.read_resource::<geom::Map>().get(coord);
                 match found {
@@ -436,13 +803,88 @@ impl Mode for PlaceLink {
                         if !graph::can_link(world, self.0, ent) {
                             return TopAction::AsEvent
                         }
-                        graph::make_link(world, self.0, ent);
+                        command::issue(world, Command::MakeLink { from: self.0.into(), to: ent.into() });
                         TopAction::Pop
                     },
                     _ => TopAction::AsEvent,
                 }
             },
-            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => TopAction::Pop,
+            Input::Press { action: Action::Cancel, .. } => TopAction::Pop,
+            _ => TopAction::AsEvent,
+        }
+    }
+}
+
+/// Click on a link, or the node at its other end, to remove it.
+struct RemoveLink(Entity);
+
+impl Mode for RemoveLink {
+    fn name(&self) -> &str { "remove link" }
+    fn on_show(&mut self, world: &mut World) {
+        world.write_resource::<MouseWidget>().kind = MWKind::Highlight;
+    }
+    fn on_hide(&mut self, world: &mut World) {
+        world.write_resource::<MouseWidget>().kind = MWKind::None;
+    }
+    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, input: Input) -> TopAction {
+        match input {
+            Input::Press { action: Action::Select, x, y } => {
+                let coord = pixel_to_coord(ctx, x, y);
+                let found = if let Some(e) = world.read_resource::<geom::Map>().get(coord) { e }
+                else { return TopAction::AsEvent };
+                let link = {
+                    let nodes = world.read_storage::<graph::Node>();
+                    let links = world.read_storage::<graph::Link>();
+                    if let Some(link) = links.get(found) {
+                        if link.from == self.0 || link.to == self.0 { Some(found) } else { None }
+                    } else {
+                        nodes.get(self.0).and_then(|n| n.link_to(found))
+                    }
+                };
+                match link {
+                    Some(link) => {
+                        command::issue(world, Command::DeleteLink { link: link.into() });
+                        TopAction::Pop
+                    },
+                    None => TopAction::AsEvent,
+                }
+            },
+            Input::Press { action: Action::Cancel, .. } => TopAction::Pop,
+            _ => TopAction::AsEvent,
+        }
+    }
+}
+
+/// Click on a pylon in range to link it to this one, or on a linked pylon to
+/// cut the link.
+struct EditGridLinks(Entity);
+
+impl Mode for EditGridLinks {
+    fn name(&self) -> &str { "edit grid links" }
+    fn on_show(&mut self, world: &mut World) {
+        world.write_resource::<MouseWidget>().kind = MWKind::Highlight;
+    }
+    fn on_hide(&mut self, world: &mut World) {
+        world.write_resource::<MouseWidget>().kind = MWKind::None;
+    }
+    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, input: Input) -> TopAction {
+        match input {
+            Input::Press { action: Action::Select, x, y } => {
+                let coord = pixel_to_coord(ctx, x, y);
+                let found = if let Some(e) = world.read_resource::<geom::Map>().get(coord) { e }
+                else { return TopAction::AsEvent };
+                let (from, to) = (self.0.into(), found.into());
+                let linked = world.read_resource::<power::PowerGrid>().is_linked(self.0, found);
+                if linked {
+                    command::issue(world, Command::UnlinkPylons { from, to });
+                } else if power::can_link(world, self.0, found) {
+                    command::issue(world, Command::LinkPylons { from, to });
+                } else {
+                    return TopAction::AsEvent
+                }
+                TopAction::done()
+            },
+            Input::Press { action: Action::Cancel, .. } => TopAction::Pop,
             _ => TopAction::AsEvent,
         }
     }
@@ -458,9 +900,9 @@ impl Mode for ToggleExclude {
     fn on_hide(&mut self, world: &mut World) {
         world.write_resource::<MouseWidget>().kind = MWKind::None;
     }
-    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, event: Event) -> TopAction {
-        match event {
-            Event::MouseButtonDown { x, y, .. } => {
+    fn on_top_event(&mut self, world: &mut World, ctx: &mut Context, input: Input) -> TopAction {
+        match input {
+            Input::Press { action: Action::Select, x, y } => {
                 let coord = pixel_to_coord(ctx, x, y);
                 let found = if let Some(e) = world.read_resource::<geom::Map>().get(coord) { e }
                 else { return TopAction::AsEvent };
@@ -468,12 +910,10 @@ impl Mode for ToggleExclude {
                 if world.read_storage::<graph::Node>().get(found).is_none() {
                     return TopAction::AsEvent;
                 }
-                let mut graphs = world.write_storage::<graph::AreaGraph>();
-                let exclude = &mut or_die(|| try_get_mut(&mut graphs, self.0)).exclude_mut();
-                if !exclude.remove(&found) { exclude.insert(found); }
+                command::issue(world, Command::ToggleExclude { node: self.0.into(), exclude: found.into() });
                 TopAction::Pop
             },
-            Event::KeyDown { keycode: Some(Keycode::Escape), .. } => TopAction::Pop,
+            Input::Press { action: Action::Cancel, .. } => TopAction::Pop,
             _ => TopAction::AsEvent,
         }
     }
@@ -491,6 +931,9 @@ pub enum MWKind {
     None,
     Highlight,
     PlaceNodeFrom(Coordinate),
+    /// A blueprint being laid out, and the node it'll add next, if it's
+    /// adding nodes rather than links.
+    Blueprint { nodes: Vec<Coordinate>, links: Vec<(Coordinate, Coordinate)>, from: Option<Coordinate> },
 }
 
 #[derive(Debug, Default)]
@@ -509,7 +952,7 @@ pub struct GrowTest {
 impl GrowTest {
     pub fn new() -> Self {
         GrowTest {
-            to_grow: hex2d::Direction::all().iter().cloned().collect(),
+            to_grow: hex2d::Direction::all().to_vec(),
             next_growth: 1,
         }
     }
@@ -520,10 +963,10 @@ impl GrowTest {
                 if grow.get(ent).is_some() { return Ok(()) }
                 grow.insert(ent, GrowTest::new())?;
             }
-            resource::Source::add(world, ent,
-                resource::Pool::from(vec![(Resource::H2, 6)]), 6);
+            let h2 = grow_res(&world.read_resource::<Defs>());
+            resource::Source::add(world, ent, resource::Pool::from(vec![(h2, 6)]), 6);
             let mut sink = resource::Sink::new();
-            sink.want.inc_by(Resource::H2, 6);
+            sink.want.inc_by(h2, 6);
             world.write_storage::<resource::Sink>().insert(ent, sink)?;
             Ok(())
         });
@@ -534,27 +977,53 @@ impl Component for GrowTest {
     type Storage = BTreeStorage<Self>;
 }
 
+impl Persist for GrowTest {
+    // Directions are saved as their index in `Direction::all()`.
+    type Data = (Vec<usize>, usize);
+    fn save(&self, _: &Saver) -> Result<Self::Data> {
+        let all = hex2d::Direction::all();
+        let dirs = self.to_grow.iter()
+            .map(|d| all.iter().position(|a| a == d).unwrap())
+            .collect();
+        Ok((dirs, self.next_growth))
+    }
+    fn load((dirs, next_growth): Self::Data, _: &Loader) -> Result<Self> {
+        let all = hex2d::Direction::all();
+        Ok(GrowTest {
+            to_grow: dirs.into_iter().map(|ix| all[ix]).collect(),
+            next_growth,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct RunGrowTest;
 
 #[derive(shred_derive::SystemData)]
 pub struct GrowTestData<'a> {
+    defs: ReadExpect<'a, Defs>,
     entities: Entities<'a>,
     nodes: WriteStorage<'a, graph::Node>,
     grow: WriteStorage<'a, GrowTest>,
     sinks: WriteStorage<'a, resource::Sink>,
-    lazy: Read<'a, LazyUpdate>,
+    lazy: Write<'a, LazyUpdate>,
 }
 
 const GROW_LEN: usize = 5;
+const GROW_RES: &str = "H2";
+
+fn grow_res(defs: &Defs) -> Resource {
+    defs.find_res(GROW_RES).expect("no H2 defined")
+}
 
 impl<'a> System<'a> for RunGrowTest {
     type SystemData = GrowTestData<'a>;
 
     fn run(&mut self, mut data: Self::SystemData) {
+        let h2 = grow_res(&data.defs);
         let mut to_grow: Vec<(Entity, Coordinate, Coordinate)> = vec![];
         for (ent, node, sink, grow) in (&*data.entities, &mut data.nodes, &mut data.sinks, &mut data.grow).join() {
-            if sink.has.get(Resource::H2) < grow.next_growth { continue }
+            if sink.has.get(h2) < grow.next_growth { continue }
             let next_dir = if let Some(d) = grow.to_grow.pop() { d } else { continue };
             let mut next_coord: Coordinate = node.at();
             for _ in 0..GROW_LEN {
@@ -581,17 +1050,18 @@ impl<'a> System<'a> for RunGrowTest {
 fn pixel_to_coord(ctx: &Context, mx: i32, my: i32) -> Coordinate {
     // TODO: there *has* to be a more direct way to do this - multiply by transform
     // matrix or something - but the types involved there are baffling.
-    let rel_mx: f32 = (mx as f32) / (super::WINDOW_WIDTH as f32);
-    let rel_my: f32 = (my as f32) / (super::WINDOW_HEIGHT as f32);
+    let (win_w, win_h) = graphics::get_size(ctx);
+    let rel_mx: f32 = (mx as f32) / (win_w as f32);
+    let rel_my: f32 = (my as f32) / (win_h as f32);
     let graphics::Rect { x, y, w, h } = graphics::get_screen_coordinates(ctx);
     let scr_mx: f32 = x + (w * rel_mx);
     let scr_my: f32 = y + (h * rel_my);
     Coordinate::from_pixel(scr_mx, scr_my, draw::SPACING)
 }
 
-fn handle_node_selection(world: &mut World, ctx: &Context, event: &Event) -> Option<Entity> {
-    match *event {
-        Event::MouseMotion { x, y, .. } => {
+fn handle_node_selection(world: &mut World, ctx: &Context, input: Input) -> Option<Entity> {
+    match input {
+        Input::Motion { x, y } => {
             let coord = pixel_to_coord(ctx, x, y);
             let valid = match world.read_resource::<geom::Map>().get(coord) {
                 Some(ent) => world.read_storage::<graph::Node>().get(ent).is_some(),
@@ -600,7 +1070,7 @@ fn handle_node_selection(world: &mut World, ctx: &Context, event: &Event) -> Opt
             world.write_resource::<MouseWidget>().valid = valid;
             None
         },
-        Event::MouseButtonDown { x, y, .. } => {
+        Input::Press { action: Action::Select, x, y } => {
             let coord = pixel_to_coord(ctx, x, y);
             match world.read_resource::<geom::Map>().get(coord) {
                 Some(ent) if world.read_storage::<graph::Node>().get(ent).is_some() => {
@@ -9,6 +9,7 @@ use ggez::{
 };
 use hex2d::Coordinate;
 use hibitset::BitSet;
+use serde_derive::{Deserialize, Serialize};
 use spade::{
     rtree::RTree,
     BoundingRect,
@@ -22,6 +23,7 @@ use specs::{
 use crate::draw;
 use crate::graph;
 use crate::error::{Error, Result, or_die};
+use crate::save::{self, Loader, Persist, Saver};
 use crate::util::*;
 
 #[derive(Debug)]
@@ -41,16 +43,50 @@ impl Motion {
         let dist = nalgebra::distance(&from, &to);
         /* Hex center to hex center is 2 * altitude of equilateral triangle */
         let speed_scale = 3.0f32.sqrt() * draw::HEX_SIDE;
-        let inc = (speed * speed_scale * super::UPDATE_DELTA) / dist;
+        // Staying put is instantly done.
+        let inc = if dist > 0.0 { (speed * speed_scale * super::UPDATE_DELTA) / dist } else { 1.0 };
         Motion { from, to, inc, at: 0.0 }
     }
+    /// The hex currently being passed over.
+    pub fn coord(&self) -> Coordinate {
+        let pos = self.from + (self.to - self.from)*self.at.min(1.0);
+        Coordinate::from_pixel(pos.x, pos.y, draw::SPACING)
+    }
 }
 
 impl Component for Motion {
     type Storage = BTreeStorage<Self>;
 }
 
-#[derive(Debug, Default)]
+#[derive(Serialize, Deserialize)]
+pub struct MotionData {
+    from: (f32, f32),
+    to: (f32, f32),
+    inc: f32,
+    at: f32,
+}
+
+impl Persist for Motion {
+    type Data = MotionData;
+    fn save(&self, _: &Saver) -> Result<MotionData> {
+        Ok(MotionData {
+            from: (self.from.x, self.from.y),
+            to: (self.to.x, self.to.y),
+            inc: self.inc,
+            at: self.at,
+        })
+    }
+    fn load(data: MotionData, _: &Loader) -> Result<Self> {
+        Ok(Motion {
+            from: Point2::new(data.from.0, data.from.1),
+            to: Point2::new(data.to.0, data.to.1),
+            inc: data.inc,
+            at: data.at,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct MotionDone;
 
 impl Component for MotionDone {
@@ -99,12 +135,33 @@ impl Component for Space {
     type Storage = BTreeStorage<Self>;
 }
 
+impl Persist for Space {
+    type Data = Vec<save::Coord>;
+    fn save(&self, _: &Saver) -> Result<Self::Data> {
+        let mut coords: Vec<save::Coord> = self.0.iter().cloned().map(save::to_coord).collect();
+        coords.sort();
+        Ok(coords)
+    }
+    fn load(data: Self::Data, _: &Loader) -> Result<Self> {
+        Ok(Space::new(data.into_iter().map(save::from_coord)))
+    }
+}
+
 #[derive(Debug)]
 pub struct Map(HashMap<Coordinate, Entity>);
 
 impl Map {
     pub fn new() -> Self { Map(HashMap::new()) }
+    pub fn from_spaces(entities: &Entities, spaces: &ReadStorage<Space>) -> Self {
+        let mut map = HashMap::new();
+        for (entity, space) in (&**entities, spaces).join() {
+            for &c in space.coords() { map.insert(c, entity); }
+        }
+        Map(map)
+    }
     pub fn get(&self, coord: Coordinate) -> Option<Entity> { self.0.get(&coord).cloned() }
+    /// Occupied cells.
+    pub fn len(&self) -> usize { self.0.len() }
     pub fn is_occupied(&self, space: &Space) -> bool {
         space.coords().iter().any(|c| self.0.get(c).is_some())
     }
@@ -120,7 +177,6 @@ impl Map {
         for c in coords { self.0.insert(c, ent); }
         Ok(())
     }
-    #[allow(unused)]
     pub fn clear(
         &mut self, locs: &mut WriteStorage<Space>,
         ent: Entity,
@@ -209,7 +265,6 @@ impl AreaMap {
     {
         self.0.insert(Area::new(center, radius, entity, TypeId::of::<T>()))
     }
-    #[allow(unused)]
     pub fn remove<T>(&mut self, center: Coordinate, radius: i32, entity: Entity) -> bool
         where T: 'static + ?Sized
     {
@@ -252,7 +307,7 @@ impl<T> AreaBuilder<T> {
         where AreaWatch<T>: Component
     {
         let mut exclude = HashSet::new();
-        exclude.insert(self.entity.clone());
+        exclude.insert(self.entity);
         world.write_storage().insert(self.entity, AreaWatch {
             range: self.range, exclude, data: self.data,
         })?;
@@ -262,6 +317,11 @@ impl<T> AreaBuilder<T> {
 }
 
 impl<T> AreaWatch<T> {
+    /// Reassembles a watch from saved state; the caller is responsible for
+    /// registering it with the `AreaMap`.
+    pub fn from_parts(range: i32, exclude: HashSet<Entity>, data: T) -> Self {
+        AreaWatch { range, exclude, data }
+    }
     pub fn range(&self) -> i32 { self.range }
     pub fn exclude(&self) -> &HashSet<Entity> { &self.exclude }
     pub fn exclude_mut(&mut self) -> &mut HashSet<Entity> { &mut self.exclude }
@@ -297,4 +357,34 @@ impl AreaSet {
 
 impl Component for AreaSet {
     type Storage = BTreeStorage<Self>;
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AreaSetData {
+    range: i32,
+    exclude: Vec<u32>,
+    nodes: Vec<u32>,
+}
+
+/// Sorted, so that the same world always saves the same.
+pub fn save_exclude(exclude: &HashSet<Entity>, saver: &Saver) -> Result<Vec<u32>> {
+    let mut ids: Vec<u32> = exclude.iter().map(|&e| saver.id(e)).collect::<Result<_>>()?;
+    ids.sort();
+    Ok(ids)
+}
+
+impl Persist for AreaSet {
+    type Data = AreaSetData;
+    fn save(&self, saver: &Saver) -> Result<AreaSetData> {
+        let mut nodes: Vec<u32> = self.data.iter().map(|&e| saver.id(e)).collect::<Result<_>>()?;
+        nodes.sort();
+        Ok(AreaSetData { range: self.range, exclude: save_exclude(&self.exclude, saver)?, nodes })
+    }
+    fn load(data: AreaSetData, loader: &Loader) -> Result<Self> {
+        Ok(AreaWatch {
+            range: data.range,
+            exclude: data.exclude.into_iter().map(|id| loader.entity(id)).collect::<Result<_>>()?,
+            data: data.nodes.into_iter().map(|id| loader.entity(id)).collect::<Result<_>>()?,
+        })
+    }
 }
\ No newline at end of file
@@ -59,7 +59,8 @@ impl ImGuiContext {
     }
 
     pub fn frame<'ui, 'a: 'ui>(&'a mut self, ctx: &mut Context) -> ImGuiFrame<'ui> {
-        let graphics::Rect { w, h, .. } = graphics::get_screen_coordinates(ctx);
+        // Window pixels, whatever the camera is showing.
+        let (w, h) = graphics::get_size(ctx);
         let fs = imgui::FrameSize { logical_size: (w.into(), h.into()), hidpi_factor: 1.0 };
         ImGuiFrame {
             ui: self.imgui.frame(fs, duration_f32(timer::get_delta(ctx))),
@@ -72,8 +73,8 @@ impl ImGuiContext {
             Event::MouseMotion { x, y, .. } => self.imgui.set_mouse_pos(*x as f32, *y as f32),
             _ => {
                 if let Some((ix, state)) = match event {
-                    Event::MouseButtonDown { mouse_btn, .. } => mb_ix(mouse_btn).map(|ix| (ix, true)),
-                    Event::MouseButtonUp { mouse_btn, .. } => mb_ix(mouse_btn).map(|ix| (ix, false)),
+                    Event::MouseButtonDown { mouse_btn, .. } => mb_ix(*mouse_btn).map(|ix| (ix, true)),
+                    Event::MouseButtonUp { mouse_btn, .. } => mb_ix(*mouse_btn).map(|ix| (ix, false)),
                     _ => None,
                 } {
                     self.mouse_down[ix] = state;
@@ -84,7 +85,7 @@ impl ImGuiContext {
     }
 }
 
-fn mb_ix(mb: &MouseButton) -> Option<usize> {
+fn mb_ix(mb: MouseButton) -> Option<usize> {
     match mb {
         MouseButton::Left => Some(0),
         MouseButton::Right => Some(1),
@@ -1,7 +1,7 @@
 use std::{
     cmp::max,
     collections::{
-        HashSet, HashMap,
+        BTreeMap, HashSet, HashMap,
     },
 };
 
@@ -11,18 +11,22 @@ use petgraph::{
     self,
     graphmap::GraphMap,
 };
+use serde_derive::{Deserialize, Serialize};
 use specs::{
     prelude::*,
     storage::{BTreeStorage, GenericReadStorage},
     Component,
 };
 
+use crate::demolish;
 use crate::draw;
 use crate::error::{
     Error, Result,
     or_die,
 };
 use crate::geom;
+use crate::power;
+use crate::save::{self, Loader, Persist, Saver};
 use crate::util::*;
 
 type GraphData = GraphMap<Entity, Entity, petgraph::Undirected>;
@@ -50,12 +54,16 @@ impl Graph {
         self.data.add_edge(from, to, link_ent);
         self.route_cache.clear();
     }
-    #[allow(unused)] // TODO
     fn remove_link(&mut self, from: Entity, to: Entity) -> Option<Entity> {
         let ret = self.data.remove_edge(from, to);
         if ret.is_some() { self.route_cache.clear() }
         ret
     }
+    fn remove_node(&mut self, node: Entity) -> bool {
+        let ret = self.data.remove_node(node);
+        if ret { self.route_cache.clear() }
+        ret
+    }
     pub fn nodes_route<'a>(&'a mut self) -> (impl Iterator<Item=Entity> + 'a, Router<'a>) {
         (self.data.nodes(), Router { data: &self.data, route_cache: &mut self.route_cache })
     }
@@ -89,7 +97,7 @@ fn calc_route(
             /* start= */ from,
             /* is_goal= */ |ent| { ent == to },
             /* edge_cost= */ |(_, _, &link_ent)| or_die(|| {
-                Ok(try_get(links, link_ent)?.path.len())
+                Ok(try_get(links, link_ent)?.cost())
             }),
             /* estimate_cost= */ |ent| or_die(|| {
                 let ent_coord = try_get(nodes, ent)?.at;
@@ -117,7 +125,7 @@ pub type AreaGraph = geom::AreaWatch<Graph>;
 
 impl AreaGraph {
     pub fn add(world: &mut World, parent: Entity, range: i32) -> Result<()> {
-        let res = {
+        let area = {
             let nodes = world.read_storage::<Node>();
             let entities = world.entities();
             Self::build(world, parent, range, |found| {
@@ -134,8 +142,8 @@ impl AreaGraph {
                 }
                 graph
             })
-        }?.insert(world);
-        res
+        }?;
+        area.insert(world)
     }
     pub fn nodes_route<'a>(&'a mut self) -> (impl Iterator<Item=Entity> + 'a, Router<'a>) {
         let ex = &self.exclude;
@@ -148,7 +156,43 @@ impl Component for AreaGraph {
     type Storage = DenseVecStorage<Self>;
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize)]
+pub struct AreaGraphData {
+    range: i32,
+    exclude: Vec<u32>,
+    // Nodes and edges are kept in graph order so that route search
+    // tie-breaks the same way after a load.
+    nodes: Vec<u32>,
+    edges: Vec<(u32, u32, u32)>,
+}
+
+impl Persist for AreaGraph {
+    type Data = AreaGraphData;
+    fn save(&self, saver: &Saver) -> Result<AreaGraphData> {
+        let mut nodes = vec![];
+        for node in self.data.data.nodes() { nodes.push(saver.id(node)?); }
+        let mut edges = vec![];
+        for (from, to, &link) in self.data.data.all_edges() {
+            edges.push((saver.id(from)?, saver.id(to)?, saver.id(link)?));
+        }
+        Ok(AreaGraphData {
+            range: self.range(),
+            exclude: geom::save_exclude(&self.exclude, saver)?,
+            nodes, edges,
+        })
+    }
+    fn load(data: AreaGraphData, loader: &Loader) -> Result<Self> {
+        let mut graph = Graph::new();
+        for id in data.nodes { graph.data.add_node(loader.entity(id)?); }
+        for (from, to, link) in data.edges {
+            graph.add_link_to(loader.entity(from)?, loader.entity(to)?, loader.entity(link)?);
+        }
+        let exclude = data.exclude.into_iter().map(|id| loader.entity(id)).collect::<Result<_>>()?;
+        Ok(geom::AreaWatch::from_parts(data.range, exclude, graph))
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum PathDir {
     Fwd,
     Rev,
@@ -157,37 +201,130 @@ pub enum PathDir {
 #[derive(Debug)]
 pub struct Node {
     at: Coordinate,
-    links: HashMap</* Node */ Entity,/* Link */ Entity>,
+    // Ordered, since graphs are built by walking this.
+    links: BTreeMap</* Node */ Entity,/* Link */ Entity>,
 }
 
 impl Node {
     pub fn at(&self) -> Coordinate { self.at }
+    pub fn links<'a>(&'a self) -> impl Iterator<Item=Entity> + 'a { self.links.values().cloned() }
+    pub fn link_to(&self, node: Entity) -> Option<Entity> { self.links.get(&node).cloned() }
+    pub fn neighbors<'a>(&'a self) -> impl Iterator<Item=Entity> + 'a { self.links.keys().cloned() }
 }
 
 impl Component for Node {
     type Storage = DenseVecStorage<Self>;
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct NodeData {
+    at: save::Coord,
+    links: Vec<(u32, u32)>,
+}
+
+impl Persist for Node {
+    type Data = NodeData;
+    fn save(&self, saver: &Saver) -> Result<NodeData> {
+        let mut links = vec![];
+        for (&node, &link) in &self.links {
+            links.push((saver.id(node)?, saver.id(link)?));
+        }
+        Ok(NodeData { at: save::to_coord(self.at), links })
+    }
+    fn load(data: NodeData, loader: &Loader) -> Result<Self> {
+        let mut links = BTreeMap::new();
+        for (node, link) in data.links {
+            links.insert(loader.entity(node)?, loader.entity(link)?);
+        }
+        Ok(Node { at: save::from_coord(data.at), links })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Link {
     pub from: Entity,
     pub to: Entity,
     pub path: Vec<Coordinate>,
+    // Packets currently on the link, and the most it can hold at once.
+    load: usize,
+    capacity: usize,
+    // `load` as of the last route refresh; routing uses this rather than the
+    // live value so that cached routes stay valid until the next refresh.
+    congestion: usize,
+}
+
+const HEXES_PER_PACKET: usize = 2;
+
+impl Link {
+    fn new(from: Entity, to: Entity, path: Vec<Coordinate>) -> Self {
+        let capacity = max(1, path.len() / HEXES_PER_PACKET);
+        Link { from, to, path, load: 0, capacity, congestion: 0 }
+    }
+    pub fn load(&self) -> usize { self.load }
+    pub fn capacity(&self) -> usize { self.capacity }
+    /// A full link costs twice its length to route through.
+    fn cost(&self) -> usize {
+        let len = self.path.len();
+        len + (len * self.congestion) / self.capacity
+    }
+    fn enter(&mut self) -> bool {
+        if self.load >= self.capacity { return false }
+        self.load += 1;
+        true
+    }
+    pub fn leave(&mut self) {
+        debug_assert!(self.load > 0, "leaving a link nothing entered");
+        self.load = self.load.saturating_sub(1);
+    }
 }
 
 impl Component for Link {
     type Storage = BTreeStorage<Self>;
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Serialize, Deserialize)]
+pub struct LinkData {
+    from: u32,
+    to: u32,
+    path: Vec<save::Coord>,
+    load: usize,
+    capacity: usize,
+    congestion: usize,
+}
+
+impl Persist for Link {
+    type Data = LinkData;
+    fn save(&self, saver: &Saver) -> Result<LinkData> {
+        Ok(LinkData {
+            from: saver.id(self.from)?,
+            to: saver.id(self.to)?,
+            path: self.path.iter().cloned().map(save::to_coord).collect(),
+            load: self.load,
+            capacity: self.capacity,
+            congestion: self.congestion,
+        })
+    }
+    fn load(data: LinkData, loader: &Loader) -> Result<Self> {
+        Ok(Link {
+            from: loader.entity(data.from)?,
+            to: loader.entity(data.to)?,
+            path: data.path.into_iter().map(save::from_coord).collect(),
+            load: data.load,
+            capacity: data.capacity,
+            congestion: data.congestion,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 enum PathCoord {
     More,
     End,
 }
 
-fn path_ix(
+fn path_ix<S: GenericReadStorage<Component=Link>>(
     (link_ent, path_dir): (Entity, PathDir), ix: usize,
-    links: &ReadStorage<Link>,
+    links: &S,
 ) -> Result<(Coordinate, PathCoord)> {
     let link = try_get(links, link_ent)?;
     let coord_ix = match path_dir {
@@ -210,17 +347,45 @@ pub struct FollowRoute {
     link_ix: usize,
     coord_ix: usize,
     phase: RoutePhase,
+    // What's needed to re-plan if the route breaks: the AreaGraph it was
+    // planned in, where it's going, and the last node it passed through.
+    graph: Option<Entity>,
+    dest: Entity,
+    last_node: Entity,
 }
 
 #[derive(Debug, Copy, Clone)]
 enum RoutePhase {
     ToLink(Coordinate, PathCoord),
-    ToNode(Coordinate),
+    ToNode(Coordinate, Entity),
+    // Waiting at `last_node` for room on the next link.
+    AtNode(Coordinate),
 }
 
 impl FollowRoute {
-    fn new(route: Route, speed: f32, phase: RoutePhase) -> Self {
-        FollowRoute { route, speed, link_ix: 0, coord_ix: 0, phase }
+    fn new(route: Route, speed: f32, phase: RoutePhase, graph: Entity, from: Entity, to: Entity) -> Self {
+        FollowRoute {
+            route, speed, link_ix: 0, coord_ix: 0, phase,
+            graph: Some(graph), dest: to, last_node: from,
+        }
+    }
+    pub fn graph(&self) -> Option<Entity> { self.graph }
+    /// The node it's at or last left.
+    pub fn last_node(&self) -> Entity { self.last_node }
+    /// Forget the planning graph, if it's `owner`'s.  The route can still be
+    /// followed, but not re-planned.
+    pub fn disown(&mut self, owner: Entity) {
+        if self.graph == Some(owner) { self.graph = None }
+    }
+    /// The link being traversed, if not waiting at a node.
+    pub fn current_link(&self) -> Option<Entity> {
+        match self.phase {
+            RoutePhase::AtNode(_) => None,
+            _ => self.route.get(self.link_ix).map(|&(l, _)| l),
+        }
+    }
+    fn intact<S: GenericReadStorage<Component=Link>>(&self, links: &S) -> bool {
+        self.route[self.link_ix..].iter().all(|&(l, _)| links.get(l).is_some())
     }
 }
 
@@ -228,29 +393,105 @@ impl Component for FollowRoute {
     type Storage = BTreeStorage<Self>;
 }
 
-#[derive(Debug, Default)]
+#[derive(Serialize, Deserialize)]
+enum RoutePhaseData {
+    ToLink(save::Coord, PathCoord),
+    ToNode(save::Coord, u32),
+    AtNode(save::Coord),
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FollowRouteData {
+    route: Vec<(u32, PathDir)>,
+    speed: f32,
+    link_ix: usize,
+    coord_ix: usize,
+    phase: RoutePhaseData,
+    graph: Option<u32>,
+    dest: u32,
+    last_node: u32,
+}
+
+impl Persist for FollowRoute {
+    type Data = FollowRouteData;
+    fn save(&self, saver: &Saver) -> Result<FollowRouteData> {
+        let mut route = vec![];
+        for &(link, dir) in &self.route { route.push((saver.id(link)?, dir)); }
+        Ok(FollowRouteData {
+            route,
+            speed: self.speed,
+            link_ix: self.link_ix,
+            coord_ix: self.coord_ix,
+            phase: match self.phase {
+                RoutePhase::ToLink(c, p) => RoutePhaseData::ToLink(save::to_coord(c), p),
+                RoutePhase::ToNode(c, n) => RoutePhaseData::ToNode(save::to_coord(c), saver.id(n)?),
+                RoutePhase::AtNode(c) => RoutePhaseData::AtNode(save::to_coord(c)),
+            },
+            graph: match self.graph { Some(g) => Some(saver.id(g)?), None => None },
+            dest: saver.id(self.dest)?,
+            last_node: saver.id(self.last_node)?,
+        })
+    }
+    fn load(data: FollowRouteData, loader: &Loader) -> Result<Self> {
+        let mut route = vec![];
+        for (link, dir) in data.route { route.push((loader.entity(link)?, dir)); }
+        Ok(FollowRoute {
+            route,
+            speed: data.speed,
+            link_ix: data.link_ix,
+            coord_ix: data.coord_ix,
+            phase: match data.phase {
+                RoutePhaseData::ToLink(c, p) => RoutePhase::ToLink(save::from_coord(c), p),
+                RoutePhaseData::ToNode(c, n) => RoutePhase::ToNode(save::from_coord(c), loader.entity(n)?),
+                RoutePhaseData::AtNode(c) => RoutePhase::AtNode(save::from_coord(c)),
+            },
+            graph: match data.graph { Some(g) => Some(loader.entity(g)?), None => None },
+            dest: loader.entity(data.dest)?,
+            last_node: loader.entity(data.last_node)?,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct RouteDone;
 
 impl Component for RouteDone {
     type Storage = NullStorage<Self>;
 }
 
+/// Set on a packet that's reached a node and found the rest of its route
+/// gone; `Reroute` picks it up in the same update.
+#[derive(Debug, Default)]
+pub struct RouteBroken;
+
+impl Component for RouteBroken {
+    type Storage = NullStorage<Self>;
+}
+
 #[derive(Debug)]
 pub struct Traverse;
 
 impl Traverse {
+    /// `graph` is the entity whose AreaGraph planned `route`, from node
+    /// `from` to node `to`.
     pub fn start(
         world: &mut World,
         entity: Entity,
-        start: Coordinate,
+        graph: Entity,
+        from: Entity,
+        to: Entity,
         route: Route,
         speed: f32,
     ) {
         or_die(|| {
-            let (first_coord, p) = path_ix(route[0], 0, &world.read_storage::<Link>())?;
-            let follow = FollowRoute::new(route, speed, RoutePhase::ToLink(first_coord, p));
-            world.write_storage::<geom::Motion>().insert(entity,
-                geom::Motion::new(start, first_coord, follow.speed))?;
+            // Packets start out waiting at the node, so that they only set
+            // off once there's room on the first link.
+            let start = try_get(&world.read_storage::<Node>(), from)?.at;
+            let follow = FollowRoute::new(route, speed, RoutePhase::AtNode(start), graph, from, to);
+            let mut motion = geom::Motion::new(start, start, follow.speed);
+            motion.at = 1.0;
+            world.write_storage::<geom::Motion>().insert(entity, motion)?;
+            world.write_storage::<geom::MotionDone>().insert(entity, geom::MotionDone)?;
             world.write_storage::<FollowRoute>().insert(entity, follow)?;
             Ok(())
         })
@@ -260,12 +501,13 @@ impl Traverse {
 #[derive(shred_derive::SystemData)]
 pub struct TraverseData<'a> {
     entities: Entities<'a>,
-    links: ReadStorage<'a, Link>,
+    links: WriteStorage<'a, Link>,
     nodes: ReadStorage<'a, Node>,
     motions: WriteStorage<'a, geom::Motion>,
     motion_done: WriteStorage<'a, geom::MotionDone>,
     routes: WriteStorage<'a, FollowRoute>,
     route_done: WriteStorage<'a, RouteDone>,
+    broken: WriteStorage<'a, RouteBroken>,
 }
 
 impl<'a> System<'a> for Traverse {
@@ -274,9 +516,10 @@ impl<'a> System<'a> for Traverse {
     fn run(&mut self, mut data: Self::SystemData) {
         let mut more_motion = Vec::new();
         let mut no_more_route = Vec::new();
-        for (entity, motion, route, _, ()) in (
+        let mut broken = Vec::new();
+        for (entity, motion, route, _, (), ()) in (
             &*data.entities, &mut data.motions, &mut data.routes,
-            &data.motion_done, !&data.route_done).join() {
+            &data.motion_done, !&data.route_done, !&data.broken).join() {
             /* Given the phase of motion that has finished,
                 where is it now, and what's the next phase? */
             let (from_coord, link_next) = match route.phase {
@@ -290,23 +533,39 @@ impl<'a> System<'a> for Traverse {
                     };
                     (c, l)
                 },
-                RoutePhase::ToNode(c) => {
+                RoutePhase::ToNode(c, node) => {
+                    if let Some(link) = data.links.get_mut(route.route[route.link_ix].0) {
+                        link.leave();
+                    }
                     route.coord_ix = 0;
                     route.link_ix += 1;
-                    if route.link_ix >= route.route.len() {
-                        no_more_route.push(entity);
-                        continue
-                    }
+                    route.last_node = node;
+                    route.phase = RoutePhase::AtNode(c);
                     (c, true)
                 },
+                RoutePhase::AtNode(c) => (c, true),
             };
+            /* At a node, it has to get onto the next link before moving on. */
+            if let RoutePhase::AtNode(_) = route.phase {
+                if route.link_ix >= route.route.len() {
+                    no_more_route.push(entity);
+                    continue
+                }
+                if !route.intact(&data.links) {
+                    broken.push(entity);
+                    continue
+                }
+                let link_ent = route.route[route.link_ix].0;
+                let links = &mut data.links;
+                let entered = or_die(|| Ok(try_get_mut(links, link_ent)?.enter()));
+                if !entered { continue }
+            }
             /* And given the new phase, where is it going? */
             let to_coord = {
                 let links = &data.links;
                 let nodes = &data.nodes;
                 or_die(|| {
                     if link_next {
-                        // TODO: This can fail when a link is deleted.  Detect and tag.
                         let (coord, more) = path_ix(
                             route.route[route.link_ix],
                             route.coord_ix,
@@ -322,7 +581,7 @@ impl<'a> System<'a> for Traverse {
                             PathDir::Rev => link.from,
                         };
                         let coord = try_get(nodes, node_ent)?.at;
-                        route.phase = RoutePhase::ToNode(coord);
+                        route.phase = RoutePhase::ToNode(coord, node_ent);
                         Ok(coord)
                     }
                 })
@@ -339,11 +598,98 @@ impl<'a> System<'a> for Traverse {
             for entity in no_more_route {
                 data.route_done.insert(entity, RouteDone)?;
             }
+            for entity in broken {
+                data.broken.insert(entity, RouteBroken)?;
+            }
             Ok(())
         });
     }
 }
 
+#[derive(Debug)]
+pub struct Reroute;
+
+#[derive(shred_derive::SystemData)]
+pub struct RerouteData<'a> {
+    entities: Entities<'a>,
+    links: ReadStorage<'a, Link>,
+    nodes: ReadStorage<'a, Node>,
+    graphs: WriteStorage<'a, AreaGraph>,
+    routes: WriteStorage<'a, FollowRoute>,
+    broken: WriteStorage<'a, RouteBroken>,
+    lazy: Write<'a, LazyUpdate>,
+}
+
+impl<'a> System<'a> for Reroute {
+    type SystemData = RerouteData<'a>;
+
+    fn run(&mut self, mut data: Self::SystemData) {
+        let mut fixed = vec![];
+        let mut lost = vec![];
+        let (links, nodes, graphs) = (&data.links, &data.nodes, &mut data.graphs);
+        for (entity, route, _) in (&*data.entities, &mut data.routes, &data.broken).join() {
+            // Demolition takes whatever's at a node with it, but a route can't
+            // be planned from a node that's gone either way.
+            let at = if let Some(n) = nodes.get(route.last_node) { n.at } else {
+                lost.push(entity);
+                continue
+            };
+            let ag = match route.graph { Some(g) => graphs.get_mut(g), None => None };
+            let found = ag.and_then(|ag| {
+                let (_, mut router) = ag.nodes_route();
+                router.route(links, nodes, route.last_node, route.dest)
+            });
+            let new_route = if let Some((_, r)) = found { r } else {
+                lost.push(entity);
+                continue
+            };
+            // Still waiting at the node; Traverse sets off on the new route.
+            route.route = new_route;
+            route.link_ix = 0;
+            route.coord_ix = 0;
+            route.phase = RoutePhase::AtNode(at);
+            fixed.push(entity);
+        }
+        for entity in fixed {
+            data.broken.remove(entity);
+        }
+        for entity in lost {
+            data.broken.remove(entity);
+            data.lazy.exec_mut(move |world| demolish::drop_packet(world, entity));
+        }
+    }
+}
+
+const ROUTE_REFRESH: u64 = super::UPDATES_PER_SECOND as u64;
+
+/// Periodically snapshots link congestion and drops cached routes, so that
+/// new packets steer around links that have filled up.
+#[derive(Debug)]
+pub struct RefreshRoutes;
+
+impl<'a> System<'a> for RefreshRoutes {
+    type SystemData = (
+        ReadExpect<'a, super::Tick>,
+        WriteStorage<'a, Link>,
+        WriteStorage<'a, AreaGraph>,
+    );
+
+    fn run(&mut self, (tick, mut links, mut graphs): Self::SystemData) {
+        if tick.0 % ROUTE_REFRESH != 0 { return }
+        let mut changed = false;
+        for link in (&mut links).join() {
+            if link.congestion != link.load {
+                link.congestion = link.load;
+                changed = true;
+            }
+        }
+        if !changed { return }
+        for ag in (&mut graphs).join() {
+            ag.data.route_cache.clear();
+        }
+    }
+}
+
 const NODE_RADIUS: i32 = 1;
 
 pub fn node_shape(center: Coordinate) -> Vec<Coordinate> {
@@ -367,7 +713,7 @@ pub fn make_node(world: &mut World, center: Coordinate) -> Entity {
             coords: node_shape(center),
             color: graphics::Color::new(0.8, 0.8, 0.8, 1.0),
         })
-        .with(Node { at: center, links: HashMap::new() })
+        .with(Node { at: center, links: BTreeMap::new() })
         .build();
     or_die(|| world.write_resource::<geom::Map>().set(
         &mut world.write_storage::<geom::Space>(), ent,
@@ -375,13 +721,16 @@ pub fn make_node(world: &mut World, center: Coordinate) -> Entity {
     ));
     let mut areas = world.write_storage::<geom::AreaSet>();
     let map = world.read_resource::<geom::AreaMap>();
-    for (area, _) in (&mut areas, map.find(center)).join() {
-        area.data.insert(ent.clone());
+    let mut grid = world.write_resource::<power::PowerGrid>();
+    for (area_ent, area, _) in (&*world.entities(), &mut areas, map.find(center)).join() {
+        area.data.insert(ent);
+        grid.cover(area_ent, ent);
     }
 
     ent
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LinkRange(i32);
 
 impl LinkRange {
@@ -453,7 +802,7 @@ pub fn can_link(world: &World, from: Entity, to: Entity) -> bool {
 
 pub fn make_link(world: &mut World, from: Entity, to: Entity) -> Entity {
     let ls = or_die(|| LinkSpace::new(&world.read_storage::<Node>(), from, to));
-    let link = Link { from, to, path: ls.path };
+    let link = Link::new(from, to, ls.path);
     let ent = world.create_entity()
         .with(draw::Shape {
             coords: ls.shape.clone(),
@@ -480,27 +829,168 @@ pub fn make_link(world: &mut World, from: Entity, to: Entity) -> Entity {
     ent
 }
 
-#[allow(unused)]  // TODO
+/// Removes a link from the map, every graph, and its end nodes, and deletes
+/// it.  Anything still routed over it must be dealt with first.
 pub fn delete_link(world: &mut World, link_ent: Entity) {
     or_die(|| {
         world.write_resource::<geom::Map>().clear(&mut world.write_storage(), link_ent)?;
-        {
-            let links = world.read_storage::<Link>();
-            let link: &Link = try_get(&links, link_ent)?;
-            let from = try_get(&world.read_storage::<Node>(), link.from)?.at();
-            let to = try_get(&world.read_storage::<Node>(), link.to)?.at();
-            let areas = world.read_resource::<geom::AreaMap>();
-            let found_from = areas.find(from);
-            let found_to = areas.find(to);
-            let mut graphs = world.write_storage::<AreaGraph>();
-            for (ag, _) in (&mut graphs, found_from & found_to).join() {
-                ag.data.remove_link(link.from, link.to)
-                    .map_or(Err(Error::NoSuchEdge), |e| {
-                        if e == link_ent { Ok(()) }
-                        else { Err(Error::WrongEdge) }
-                    })?;
+        let (from, to) = {
+            let link = try_get(&world.read_storage::<Link>(), link_ent)?.clone();
+            (link.from, link.to)
+        };
+        // Graphs pick up links by what's in range when they're built, so
+        // check all of them rather than trusting the area map.
+        for ag in (&mut world.write_storage::<AreaGraph>()).join() {
+            if ag.data.data.edge_weight(from, to) == Some(&link_ent) {
+                ag.data.remove_link(from, to);
+            }
+        }
+        let mut nodes = world.write_storage::<Node>();
+        for &(node, other) in &[(from, to), (to, from)] {
+            if let Some(n) = nodes.get_mut(node) { n.links.remove(&other); }
+        }
+        Ok(())
+    });
+    or_die(|| Ok(world.delete_entity(link_ent)?));
+}
+
+/// Removes an unlinked node from the map and everything watching it, and
+/// deletes it.
+pub fn delete_node(world: &mut World, node_ent: Entity) {
+    or_die(|| {
+        let at = try_get(&world.read_storage::<Node>(), node_ent)?.at;
+        world.write_resource::<geom::Map>().clear(&mut world.write_storage(), node_ent)?;
+        let mut area_map = world.write_resource::<geom::AreaMap>();
+        let mut grid = world.write_resource::<power::PowerGrid>();
+        for (entity, area) in (&*world.entities(), &mut world.write_storage::<geom::AreaSet>()).join() {
+            if entity == node_ent {
+                area_map.remove::<geom::AreaSet>(at, area.range(), entity);
             }
+            if area.data.remove(&node_ent) { grid.uncover(entity, node_ent); }
+            area.exclude.remove(&node_ent);
+        }
+        for (entity, ag) in (&*world.entities(), &mut world.write_storage::<AreaGraph>()).join() {
+            if entity == node_ent {
+                area_map.remove::<AreaGraph>(at, ag.range(), entity);
+            }
+            ag.data.remove_node(node_ent);
+            ag.exclude.remove(&node_ent);
         }
         Ok(())
-    })
-}
\ No newline at end of file
+    });
+    or_die(|| Ok(world.delete_entity(node_ent)?));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::{self, Command};
+    use crate::defs::Defs;
+
+    fn seed(world: &World) -> Entity {
+        (&*world.entities(), &world.read_storage::<Node>()).join().next().unwrap().0
+    }
+
+    #[test]
+    fn demolish_node_with_packet_waiting() {
+        let mut world = crate::test_world();
+        let mut update = crate::make_update();
+        let hub = seed(&world);
+        // A second factory, so the packet's route isn't planned in the hub's
+        // graph.
+        let factory = make_node(&mut world, Coordinate { x: 5, y: 0 });
+        let kind = world.read_resource::<Defs>().start();
+        kind.make(&mut world, factory);
+        make_link(&mut world, factory, hub);
+        let source = world.read_resource::<Defs>().find("CarbonSource").unwrap();
+        command::issue(&mut world, Command::StartBuild {
+            factory: factory.into(), kind: source, fork: hub.into(), at: (0, 5),
+        });
+        let packet = (&*world.entities(), &world.read_storage::<crate::build::Packet>()).join()
+            .next().unwrap().0;
+        // Fill the link on from the hub, so the packet has to wait there.
+        let pending = world.read_storage::<crate::build::Packet>().get(packet).unwrap().target();
+        let onward = world.read_storage::<Node>().get(hub).unwrap().link_to(pending).unwrap();
+        while world.write_storage::<Link>().get_mut(onward).unwrap().enter() {}
+        for _ in 0..300 {
+            crate::step(&mut world, &mut update);
+            if world.read_storage::<FollowRoute>().get(packet).unwrap().last_node() == hub { break }
+        }
+        assert_eq!(world.read_storage::<FollowRoute>().get(packet).unwrap().last_node(), hub);
+
+        demolish::node(&mut world, hub);
+        assert!(!world.is_alive(packet));
+        for _ in 0..60 { crate::step(&mut world, &mut update); }
+    }
+
+    #[test]
+    fn link_capacity() {
+        let world = crate::test_world();
+        let hub = seed(&world);
+        let path = |len: i32| (0..len).map(|y| Coordinate { x: 0, y }).collect::<Vec<_>>();
+        assert_eq!(Link::new(hub, hub, path(1)).capacity(), 1);
+        let mut link = Link::new(hub, hub, path(8));
+        assert_eq!(link.capacity(), 4);
+        for _ in 0..4 { assert!(link.enter()); }
+        assert!(!link.enter());
+        assert_eq!(link.load(), 4);
+        link.leave();
+        assert!(link.enter());
+    }
+
+    #[test]
+    fn dropped_packet_frees_link() {
+        let mut world = crate::test_world();
+        let mut update = crate::make_update();
+        let hub = seed(&world);
+        let factory = make_node(&mut world, Coordinate { x: 5, y: 0 });
+        let kind = world.read_resource::<Defs>().start();
+        kind.make(&mut world, factory);
+        let link = make_link(&mut world, hub, factory);
+        let source = world.read_resource::<Defs>().find("CarbonSource").unwrap();
+        assert!(crate::build::deliver(&mut world, source, hub, factory));
+        let packet = (&*world.entities(), &world.read_storage::<crate::build::Delivery>()).join()
+            .next().unwrap().0;
+        for _ in 0..60 {
+            if world.read_storage::<FollowRoute>().get(packet).unwrap().current_link() == Some(link) { break }
+            crate::step(&mut world, &mut update);
+        }
+        assert_eq!(world.read_storage::<Link>().get(link).unwrap().load(), 1);
+
+        demolish::drop_packet(&mut world, packet);
+        assert_eq!(world.read_storage::<Link>().get(link).unwrap().load(), 0);
+        let factories = world.read_storage::<crate::build::Factory>();
+        assert_eq!(factories.get(hub).unwrap().built(source), 1);
+    }
+
+    #[test]
+    fn congestion_reroutes() {
+        let mut world = crate::test_world();
+        let mut update = crate::make_update();
+        let hub = seed(&world);
+        let near = make_node(&mut world, Coordinate { x: 0, y: 10 });
+        let detour = make_node(&mut world, Coordinate { x: 4, y: 5 });
+        let direct = make_link(&mut world, hub, near);
+        make_link(&mut world, hub, detour);
+        make_link(&mut world, detour, near);
+        let route = |world: &World| {
+            let mut graphs = world.write_storage::<AreaGraph>();
+            let (_, mut router) = graphs.get_mut(hub).unwrap().data.nodes_route();
+            router.route(&world.read_storage(), &world.read_storage(), hub, near).unwrap().1
+        };
+        assert_eq!(route(&world).len(), 1);
+
+        // Routing only sees a full link once congestion is next sampled.
+        let len = {
+            let mut links = world.write_storage::<Link>();
+            let link = links.get_mut(direct).unwrap();
+            while link.enter() {}
+            assert_eq!(link.cost(), link.path.len());
+            link.path.len()
+        };
+        assert_eq!(route(&world).len(), 1);
+        crate::step(&mut world, &mut update);
+        assert_eq!(world.read_storage::<Link>().get(direct).unwrap().cost(), 2 * len);
+        assert_eq!(route(&world).len(), 2);
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use specs::prelude::*;
+
+use crate::build;
+use crate::command;
+use crate::defs::Defs;
+use crate::error::Result;
+use crate::graph;
+use crate::resource::{self, Resource};
+use crate::save;
+
+/// Runs the simulation for `ticks` updates without opening a window, then
+/// prints a summary of the resulting world.
+pub fn run(opts: &super::Options, ticks: u64) -> Result<()> {
+    let mut world = super::start_world(opts)?;
+    let mut update = super::make_update();
+    for _ in 0..ticks {
+        super::step(&mut world, &mut update);
+    }
+    // Loaded and replayed worlds carry their own seed.
+    let seed = if opts.load.is_none() && opts.replay.is_none() { Some(opts.seed) } else { None };
+    report(&world, ticks, seed);
+    if let Some(path) = &opts.save {
+        save::save(&mut world, path)?;
+    }
+    if let Some(path) = &opts.record {
+        command::Recording::finish(&mut world, path)?;
+    }
+    Ok(())
+}
+
+fn report(world: &World, ticks: u64, seed: Option<u64>) {
+    let nodes = world.read_storage::<graph::Node>().join().count();
+    let links = world.read_storage::<graph::Link>().join().count();
+    let packets = world.read_storage::<resource::Packet>().join().count();
+    let building = world.read_storage::<build::Packet>().join().count();
+    let delivering = world.read_storage::<build::Delivery>().join().count();
+    if let Some(seed) = seed {
+        println!("Seed: {}", seed);
+    }
+    println!("Ticks: {}", ticks);
+    println!("Nodes: {}", nodes);
+    println!("Links: {}", links);
+    println!("Packets: {} resource, {} build, {} delivery", packets, building, delivering);
+
+    // Pools are capped, so total in plain maps.
+    let mut sources = HashMap::<Resource, usize>::new();
+    for source in world.read_storage::<resource::Source>().join() {
+        for (res, count) in source.has.iter() {
+            *sources.entry(res).or_insert(0) += count;
+        }
+    }
+    let mut sinks = HashMap::<Resource, usize>::new();
+    for sink in world.read_storage::<resource::Sink>().join() {
+        for (res, count) in sink.has.iter() {
+            *sinks.entry(res).or_insert(0) += count;
+        }
+    }
+    let defs = world.read_resource::<Defs>();
+    for res in defs.resources() {
+        println!("{}: {} in sources, {} in sinks", defs.res(res).name,
+            sources.get(&res).unwrap_or(&0), sinks.get(&res).unwrap_or(&0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::command::Command;
+    use crate::defs::DEFS_PATH;
+
+    fn temp(name: &str) -> String {
+        let file = format!("tree-of-stars-{}-{}", name, std::process::id());
+        std::env::temp_dir().join(file).to_string_lossy().into_owned()
+    }
+
+    fn options(replay: &str, save: &str) -> super::super::Options {
+        super::super::Options {
+            headless: Some(600), load: None, save: Some(save.into()), seed: 0,
+            record: None, replay: Some(replay.into()), defs: DEFS_PATH.into(),
+            settings: String::new(),
+        }
+    }
+
+    #[test]
+    fn replay_is_deterministic() {
+        let defs = Defs::load(DEFS_PATH).unwrap();
+        let mut world = crate::make_world(defs.clone(), 7);
+        world.write_resource::<command::Recording>().0 = Some(command::Script::new(7, None, &defs));
+        let mut update = crate::make_update();
+        let seed = (&*world.entities(), &world.read_storage::<graph::Node>()).join().next().unwrap().0;
+        for _ in 0..30 { crate::step(&mut world, &mut update); }
+        // The seed starts with one of these to give.
+        let kind = defs.find("CarbonSource").unwrap();
+        command::issue(&mut world, Command::StartBuild {
+            factory: seed.into(), kind, fork: seed.into(), at: (0, 5),
+        });
+        let script = temp("script");
+        command::Recording::finish(&mut world, &script).unwrap();
+
+        let (first, second) = (temp("first.sav"), temp("second.sav"));
+        run(&options(&script, &first), 600).unwrap();
+        run(&options(&script, &second), 600).unwrap();
+        let saved = fs::read_to_string(&first).unwrap();
+        assert_eq!(saved, fs::read_to_string(&second).unwrap());
+        let replayed = save::load(&first, defs).unwrap();
+        assert_eq!(replayed.read_storage::<graph::Node>().join().count(), 2);
+
+        for path in &[script, first, second] { fs::remove_file(path).unwrap(); }
+    }
+}
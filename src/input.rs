@@ -0,0 +1,226 @@
+/*
+Raw ggez events are translated into named actions before anything else sees
+them, so modes and the camera never look at a key or button directly.  Which
+keys and buttons trigger which action comes from the `bindings` section of
+the settings file; an action it leaves out keeps its default bindings, and an
+action listed with no bindings is switched off.
+
+Keys are named as SDL names them ("Escape", "P", "Left", "Keypad 4"), which
+follows the keyboard layout rather than key position.  A key name can start
+with any of "Ctrl+", "Shift+" and "Alt+" to need those held too ("Ctrl+Z");
+without them, it only fires while none are held.
+*/
+
+use std::collections::BTreeMap;
+
+use ggez::event::{
+    Event, Keycode, Mod, MouseButton,
+    LALTMOD, LCTRLMOD, LSHIFTMOD, RALTMOD, RCTRLMOD, RSHIFTMOD,
+};
+use sdl2::event::WindowEvent;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Action {
+    Select,
+    Cancel,
+    Pause,
+    AddLink,
+    RemoveLink,
+    Build,
+    Demolish,
+    Undo,
+    Redo,
+    PanLeft,
+    PanRight,
+    PanUp,
+    PanDown,
+    Drag,
+    ZoomIn,
+    ZoomOut,
+    Fullscreen,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Binding {
+    /// A key name, after any modifiers it needs.
+    Key(String),
+    /// "Left", "Right", "Middle", "X1" or "X2".
+    Mouse(String),
+    WheelUp,
+    WheelDown,
+}
+
+/// What modes and the camera see.  Positions are window pixels; a press or
+/// release carries wherever the pointer was last seen.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Input {
+    Press { action: Action, x: i32, y: i32 },
+    Release { action: Action, x: i32, y: i32 },
+    Motion { x: i32, y: i32 },
+    Resized,
+    /// The pointer left the window.
+    Left,
+}
+
+fn defaults() -> Vec<(Action, Vec<Binding>)> {
+    use self::Action::*;
+    use self::Binding::*;
+    let key = |k: &str| Key(k.into());
+    let mouse = |b: &str| Mouse(b.into());
+    vec![
+        (Select, vec![mouse("Left")]),
+        (Cancel, vec![key("Escape")]),
+        (Pause, vec![key("P")]),
+        (AddLink, vec![key("L")]),
+        (RemoveLink, vec![key("U")]),
+        (Build, vec![key("B")]),
+        (Demolish, vec![key("Delete")]),
+        (Undo, vec![key("Ctrl+Z")]),
+        (Redo, vec![key("Ctrl+Y"), key("Ctrl+Shift+Z")]),
+        (PanLeft, vec![key("Left")]),
+        (PanRight, vec![key("Right")]),
+        (PanUp, vec![key("Up")]),
+        (PanDown, vec![key("Down")]),
+        (Drag, vec![mouse("Right"), mouse("Middle")]),
+        (ZoomIn, vec![WheelUp, key("=")]),
+        (ZoomOut, vec![WheelDown, key("-")]),
+        (Fullscreen, vec![key("F11")]),
+    ]
+}
+
+// Modifier keys, as bits of a key trigger.
+const CTRL: u8 = 1;
+const SHIFT: u8 = 2;
+const ALT: u8 = 4;
+const MODIFIERS: [(&str, u8); 3] = [("Ctrl+", CTRL), ("Shift+", SHIFT), ("Alt+", ALT)];
+
+/// Left and right count the same.
+fn held(keymod: Mod) -> u8 {
+    let mut mods = 0;
+    if keymod.intersects(LCTRLMOD | RCTRLMOD) { mods |= CTRL }
+    if keymod.intersects(LSHIFTMOD | RSHIFTMOD) { mods |= SHIFT }
+    if keymod.intersects(LALTMOD | RALTMOD) { mods |= ALT }
+    mods
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Trigger {
+    /// A key and the modifiers held with it.
+    Key(i32, u8),
+    Mouse(u8),
+    WheelUp,
+    WheelDown,
+}
+
+fn mouse_ix(button: MouseButton) -> Option<u8> {
+    match button {
+        MouseButton::Left => Some(0),
+        MouseButton::Right => Some(1),
+        MouseButton::Middle => Some(2),
+        MouseButton::X1 => Some(3),
+        MouseButton::X2 => Some(4),
+        MouseButton::Unknown => None,
+    }
+}
+
+impl Trigger {
+    fn parse(binding: &Binding) -> Result<Self> {
+        Ok(match binding {
+            Binding::Key(name) => {
+                let mut key = name.as_str();
+                let mut mods = 0;
+                while let Some(&(prefix, m)) = MODIFIERS.iter().find(|&&(p, _)| key.starts_with(p)) {
+                    mods |= m;
+                    key = &key[prefix.len()..];
+                }
+                Trigger::Key(Keycode::from_name(key)
+                    .ok_or_else(|| Error::Settings(format!("no key named {:?}", name)))? as i32, mods)
+            },
+            Binding::Mouse(name) => {
+                let button = match name.as_str() {
+                    "Left" => MouseButton::Left,
+                    "Right" => MouseButton::Right,
+                    "Middle" => MouseButton::Middle,
+                    "X1" => MouseButton::X1,
+                    "X2" => MouseButton::X2,
+                    _ => return Err(Error::Settings(format!("no mouse button named {:?}", name))),
+                };
+                Trigger::Mouse(mouse_ix(button).unwrap())
+            },
+            Binding::WheelUp => Trigger::WheelUp,
+            Binding::WheelDown => Trigger::WheelDown,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Bindings {
+    actions: BTreeMap<Trigger, Vec<Action>>,
+    pointer: (i32, i32),
+}
+
+impl Bindings {
+    /// `overrides` replace the default bindings of the actions they list.
+    pub fn new(overrides: &[(Action, Vec<Binding>)]) -> Result<Self> {
+        let mut all: BTreeMap<Action, Vec<Binding>> = defaults().into_iter().collect();
+        for (action, bindings) in overrides {
+            all.insert(*action, bindings.clone());
+        }
+        let mut actions = BTreeMap::new();
+        for (action, bindings) in all {
+            for binding in &bindings {
+                actions.entry(Trigger::parse(binding)?).or_insert_with(Vec::new).push(action);
+            }
+        }
+        Ok(Bindings { actions, pointer: (0, 0) })
+    }
+
+    fn find(&self, trigger: Trigger) -> &[Action] {
+        self.actions.get(&trigger).map_or(&[], |a| a.as_slice())
+    }
+
+    pub fn translate(&mut self, event: &Event) -> Vec<Input> {
+        let (x, y) = self.pointer;
+        let press = |actions: &[Action]| -> Vec<Input> {
+            actions.iter().map(|&action| Input::Press { action, x, y }).collect()
+        };
+        let release = |actions: &[Action]| -> Vec<Input> {
+            actions.iter().map(|&action| Input::Release { action, x, y }).collect()
+        };
+        match *event {
+            Event::MouseMotion { x, y, .. } => {
+                self.pointer = (x, y);
+                vec![Input::Motion { x, y }]
+            },
+            Event::MouseButtonDown { mouse_btn, x, y, .. } => {
+                self.pointer = (x, y);
+                let actions = mouse_ix(mouse_btn).map_or(&[][..], |ix| self.find(Trigger::Mouse(ix)));
+                actions.iter().map(|&action| Input::Press { action, x, y }).collect()
+            },
+            Event::MouseButtonUp { mouse_btn, x, y, .. } => {
+                self.pointer = (x, y);
+                let actions = mouse_ix(mouse_btn).map_or(&[][..], |ix| self.find(Trigger::Mouse(ix)));
+                actions.iter().map(|&action| Input::Release { action, x, y }).collect()
+            },
+            Event::MouseWheel { y: dy, .. } if dy > 0 => press(self.find(Trigger::WheelUp)),
+            Event::MouseWheel { y: dy, .. } if dy < 0 => press(self.find(Trigger::WheelDown)),
+            Event::KeyDown { keycode: Some(key), keymod, repeat: false, .. } =>
+                press(self.find(Trigger::Key(key as i32, held(keymod)))),
+            // The modifiers may have been let go first, so this releases
+            // the key whatever's held.
+            Event::KeyUp { keycode: Some(key), .. } => {
+                let key = key as i32;
+                let actions: Vec<Action> = self.actions.range(Trigger::Key(key, 0)..=Trigger::Key(key, CTRL | SHIFT | ALT))
+                    .flat_map(|(_, a)| a.iter().cloned())
+                    .collect();
+                release(&actions)
+            },
+            Event::Window { win_event: WindowEvent::Resized(..), .. } => vec![Input::Resized],
+            Event::Window { win_event: WindowEvent::Leave, .. } => vec![Input::Left],
+            _ => vec![],
+        }
+    }
+}
@@ -1,26 +1,37 @@
+mod blueprint;
 mod build;
+mod camera;
+mod command;
+mod defs;
+mod demolish;
 mod draw;
 mod error;
 mod game;
 mod geom;
 mod ggez_imgui;
 mod graph;
+mod headless;
+mod input;
 mod mode;
 mod power;
 mod reactor;
 mod resource;
+mod save;
+mod settings;
 mod util;
 
 use std::time::{Duration, Instant};
 
 use ggez::{
-    conf, event, graphics, timer,
+    event, graphics, timer,
     Context,
 };
 use hex2d::Coordinate;
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
 use specs::prelude::*;
 
-use crate::error::Result;
+use crate::error::{Error, Result};
 
 pub const UPDATES_PER_SECOND: u32 = 60;
 pub const UPDATE_DELTA: f32 = 1.0 / (UPDATES_PER_SECOND as f32);
@@ -28,8 +39,15 @@ pub const UPDATE_DURATION: Duration = Duration::from_nanos(1_000_000_000 / (UPDA
 
 pub struct Now(pub Instant);
 pub struct Paused(pub bool);
-
-fn make_world(ctx: &mut Context) -> World {
+/// Number of updates run so far.
+pub struct Tick(pub u64);
+/// All randomness in the simulation comes from here, so that a given seed
+/// and the same inputs always produce the same world.
+pub struct Rng(pub XorShiftRng);
+
+/// Builds a World with every simulation component and resource registered,
+/// but no entities and nothing that needs a renderer.
+pub fn new_world(defs: defs::Defs) -> World {
     let mut world = World::new();
 
     world.register::<geom::Motion>();
@@ -42,6 +60,7 @@ fn make_world(ctx: &mut Context) -> World {
     world.register::<graph::AreaGraph>();
     world.register::<graph::FollowRoute>();
     world.register::<graph::RouteDone>();
+    world.register::<graph::RouteBroken>();
     world.register::<graph::LinkRange>();
 
     world.register::<resource::Source>();
@@ -56,6 +75,7 @@ fn make_world(ctx: &mut Context) -> World {
 
     world.register::<power::Power>();
     world.register::<power::Pylon>();
+    world.register::<power::Battery>();
 
     world.register::<draw::Shape>();
 
@@ -65,25 +85,53 @@ fn make_world(ctx: &mut Context) -> World {
     world.register::<build::Pending>();
     world.register::<build::Packet>();
     world.register::<build::Factory>();
+    world.register::<build::Delivery>();
+    world.register::<blueprint::Planned>();
 
+    world.add_resource(defs);
     world.add_resource(Now(Instant::now()));
     world.add_resource(Paused(false));
+    world.add_resource(Tick(0));
+    world.add_resource(Rng(XorShiftRng::seed_from_u64(0)));
     world.add_resource(geom::Map::new());
     world.add_resource(geom::AreaMap::new());
     world.add_resource(power::PowerGrid::new());
+    world.add_resource(save::Requested::default());
+    world.add_resource(command::Recording::default());
+    world.add_resource(command::Replay::default());
+    world.add_resource(command::History::default());
 
-    draw::build_sprites(&mut world, ctx);
     game::prep_world(&mut world);
 
+    world
+}
+
+/// A fresh game: `new_world` plus the starting node.
+pub fn make_world(defs: defs::Defs, seed: u64) -> World {
+    let start = defs.start();
+    let mut world = new_world(defs);
+    world.write_resource::<Rng>().0 = XorShiftRng::seed_from_u64(seed);
+
     let seed = graph::make_node(&mut world, Coordinate { x: 0, y: 0});
-    build::Kind::Seed.make(&mut world, seed);
+    start.make(&mut world, seed);
 
     world
 }
 
-fn make_update() -> Dispatcher<'static, 'static> {
+/// A fresh game from the stock definitions, for tests.
+#[cfg(test)]
+pub fn test_world() -> World {
+    make_world(defs::Defs::load(defs::DEFS_PATH).unwrap(), 0)
+}
+
+/// Systems that queue `LazyUpdate`s take it for write rather than read.  That
+/// keeps them from running concurrently, so lazily created entities - and
+/// hence entity ids and join order - come out the same on every run.
+pub fn make_update() -> Dispatcher<'static, 'static> {
     const TRAVEL: &str = "travel";
     const TRAVERSE: &str = "traverse";
+    const REROUTE: &str = "reroute";
+    const REFRESH_ROUTES: &str = "refresh_routes";
     //const SELF_PULL: &str = "self_pull";
     const PULL: &str = "pull";
     const RECEIVE: &str = "receive";
@@ -95,10 +143,13 @@ fn make_update() -> Dispatcher<'static, 'static> {
     const CLEAR_WASTE: &str = "clear_waste";
     const BUILD: &str = "build";
     const PRODUCTION: &str = "production";
+    const SUPPLY: &str = "supply";
 
     DispatcherBuilder::new()
         .with(geom::Travel, TRAVEL, &[])
         .with(graph::Traverse, TRAVERSE, &[TRAVEL])
+        .with(graph::Reroute, REROUTE, &[TRAVERSE])
+        .with(graph::RefreshRoutes, REFRESH_ROUTES, &[REROUTE])
         .with(resource::DoStorage, STORAGE, &[])
         //.with(resource::SelfPull, SELF_PULL, &[])
         .with(resource::Pull, PULL, &[/*SELF_PULL, */STORAGE])
@@ -109,34 +160,131 @@ fn make_update() -> Dispatcher<'static, 'static> {
         .with(reactor::ClearWaste, CLEAR_WASTE, &[])
         .with(build::Build, BUILD, &[])
         .with(build::Production, PRODUCTION, &[])
+        .with(blueprint::Supply, SUPPLY, &[BUILD, PRODUCTION])
         .with(game::RunGrowTest, GROW_TEST, &[])
         .build()
 }
 
-pub const WINDOW_WIDTH: u32 = 800;
-pub const WINDOW_HEIGHT: u32 = 800;
+/// Advance the simulation by a single tick.
+pub fn step(world: &mut World, update: &mut Dispatcher) {
+    command::run_replay(world);
+    world.write_resource::<Now>().0 += UPDATE_DURATION;
+    update.dispatch(&world.res);
+    world.maintain();
+    world.write_resource::<Tick>().0 += 1;
+}
+
+/// The world a session starts from: a save, a replayed script's start, or
+/// a fresh game.
+pub fn start_world(opts: &Options) -> Result<World> {
+    let replay = match &opts.replay {
+        Some(path) => Some(command::Script::read(path)?),
+        None => None,
+    };
+    let load = match &replay {
+        Some(script) => script.load.clone(),
+        None => opts.load.clone(),
+    };
+    let seed = replay.as_ref().map_or(opts.seed, |script| script.seed);
+    let defs = defs::Defs::load(&opts.defs)?;
+    let mut world = match &load {
+        Some(path) => save::load(path, defs)?,
+        None => make_world(defs, seed),
+    };
+    if let Some(script) = replay {
+        command::Replay::start(&mut world, script)?;
+    }
+    if opts.record.is_some() {
+        let script = command::Script::new(seed, load, &world.read_resource::<defs::Defs>());
+        world.write_resource::<command::Recording>().0 = Some(script);
+    }
+    Ok(world)
+}
+
+pub struct Options {
+    pub headless: Option<u64>,
+    pub load: Option<String>,
+    pub save: Option<String>,
+    pub seed: u64,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub defs: String,
+    pub settings: String,
+}
+
+fn parse_args() -> Result<Options> {
+    let mut opts = Options {
+        headless: None, load: None, save: None, seed: rand::random(),
+        record: None, replay: None, defs: defs::DEFS_PATH.into(),
+        settings: settings::SETTINGS_PATH.into(),
+    };
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => {
+                opts.record = Some(args.next()
+                    .ok_or_else(|| Error::Args("--record needs a file".into()))?);
+            },
+            "--replay" => {
+                opts.replay = Some(args.next()
+                    .ok_or_else(|| Error::Args("--replay needs a file".into()))?);
+            },
+            "--seed" => {
+                let seed = args.next()
+                    .ok_or_else(|| Error::Args("--seed needs a number".into()))?;
+                opts.seed = seed.parse::<u64>()
+                    .map_err(|_| Error::Args(format!("invalid seed {:?}", seed)))?;
+            },
+            "--defs" => {
+                opts.defs = args.next()
+                    .ok_or_else(|| Error::Args("--defs needs a file".into()))?;
+            },
+            "--settings" => {
+                opts.settings = args.next()
+                    .ok_or_else(|| Error::Args("--settings needs a file".into()))?;
+            },
+            "--load" => {
+                opts.load = Some(args.next()
+                    .ok_or_else(|| Error::Args("--load needs a file".into()))?);
+            },
+            "--save" => {
+                opts.save = Some(args.next()
+                    .ok_or_else(|| Error::Args("--save needs a file".into()))?);
+            },
+            "--headless" => {
+                let ticks = args.next()
+                    .ok_or_else(|| Error::Args("--headless needs a tick count".into()))?;
+                let ticks = ticks.parse::<u64>()
+                    .map_err(|_| Error::Args(format!("invalid tick count {:?}", ticks)))?;
+                opts.headless = Some(ticks);
+            },
+            _ => return Err(Error::Args(format!("unknown argument {:?}", arg))),
+        }
+    }
+    Ok(opts)
+}
 
 fn main() -> Result<()> {
-    let mut c = conf::Conf::default();
-    c.window_setup.title = "Tree of Stars".to_owned();
-    c.window_setup.samples = conf::NumSamples::Eight;
-    c.window_mode.width = WINDOW_WIDTH;
-    c.window_mode.height = WINDOW_HEIGHT;
+    let opts = parse_args()?;
+    if let Some(ticks) = opts.headless {
+        return headless::run(&opts, ticks)
+    }
+
+    let settings = settings::Settings::load(&opts.settings)?;
+    let c = settings.conf()?;
+    let mut fullscreen = settings.fullscreen;
+    let mut bindings = input::Bindings::new(&settings.bindings)?;
 
     let mut ctx = Context::load_from_conf("Tree of Stars", "abe.egnor@gmail.com", c)?;
-    graphics::set_screen_coordinates(&mut ctx, graphics::Rect {
-        x: (WINDOW_WIDTH as f32) / -2.0,
-        y: (WINDOW_HEIGHT as f32) / -2.0,
-        w: WINDOW_WIDTH as f32,
-        h: WINDOW_HEIGHT as f32,
-    })?;
     let mut events = event::Events::new(&ctx)?;
     let mut ui_ctx = ggez_imgui::ImGuiContext::new(&mut ctx);
 
-    let mut world = make_world(&mut ctx);
+    let mut world = start_world(&opts)?;
+    world.read_resource::<camera::Camera>().apply(&mut ctx);
+    draw::build_sprites(&mut world, &mut ctx);
     let mut update = make_update();
     let mut stack = mode::Stack::new();
-    stack.push(&mut world, Box::new(game::Play));
+    stack.push(&mut world, Box::new(game::Play::new()));
 
     let mut running = true;
     while running {
@@ -147,39 +295,78 @@ fn main() -> Result<()> {
         for event in events.poll() {
             ctx.process_event(&event);
             ui_ctx.process_event(&event);
-            match event {
-                Event::Quit { .. } => { running = false; break },
-                _ => (),
-            }
+            if let Event::Quit { .. } = event { running = false; break }
             ev_buffer.push(event);
         }
         if !running { break }
 
         let ui_frame = ui_ctx.frame(&mut ctx);
         for event in ev_buffer {
+            // Button releases always get through, so that a button let go
+            // over a window doesn't stay held.
             match event {
                 Event::MouseMotion { .. } |
                 Event::MouseButtonDown { .. } |
-                Event::MouseButtonUp { .. } |
                 Event::MouseWheel { .. } => {
                     if ui_frame.ui.want_capture_mouse() { continue }
                 },
                 _ => (),
             }
-            stack.handle_event(&mut world, &mut ctx, event);
+            for input in bindings.translate(&event) {
+                if let input::Input::Press { action: input::Action::Fullscreen, .. } = input {
+                    fullscreen = !fullscreen;
+                    graphics::set_fullscreen(&mut ctx, fullscreen)?;
+                    continue
+                }
+                if world.write_resource::<camera::Camera>().handle_input(&mut ctx, input) { continue }
+                stack.handle_input(&mut world, &mut ctx, input);
+            }
         }
+        let dt = util::duration_f32(timer::get_delta(&ctx));
+        let edge_scroll = !ui_frame.ui.want_capture_mouse();
+        let moved = world.write_resource::<camera::Camera>().update(&mut ctx, dt, edge_scroll);
+        if let Some(motion) = moved { stack.handle_input(&mut world, &mut ctx, motion); }
 
         while timer::check_update_time(&mut ctx, UPDATES_PER_SECOND) {
                 if world.read_resource::<Paused>().0 { continue }
-                world.write_resource::<Now>().0 += UPDATE_DURATION;
-                update.dispatch(&mut world.res);
-                world.maintain();
+                step(&mut world, &mut update);
         }
 
         draw::draw(&mut world, &mut ctx);
         stack.handle_ui(&mut world, &ui_frame.ui);
         //ui_frame.ui.show_demo_window(&mut true);
 
+        let request = world.write_resource::<save::Requested>().0.take();
+        match request {
+            Some(save::Request::Save) => {
+                if let Err(e) = save::save(&mut world, save::SAVE_PATH) {
+                    eprintln!("Save failed: {:?}", e);
+                }
+            },
+            Some(save::Request::Load) => {
+                let defs = world.read_resource::<defs::Defs>().clone();
+                let camera = world.read_resource::<camera::Camera>().clone();
+                match save::load(save::SAVE_PATH, defs) {
+                    Ok(loaded) => {
+                        // A script can only start from a save, not load one
+                        // partway through, so the recording ends here.
+                        if let Some(path) = &opts.record {
+                            if let Err(e) = command::Recording::finish(&mut world, path) {
+                                eprintln!("Writing recording failed: {:?}", e);
+                            }
+                        }
+                        world = loaded;
+                        *world.write_resource::<camera::Camera>() = camera;
+                        draw::build_sprites(&mut world, &mut ctx);
+                        stack = mode::Stack::new();
+                        stack.push(&mut world, Box::new(game::Play::new()));
+                    },
+                    Err(e) => eprintln!("Load failed: {:?}", e),
+                }
+            },
+            None => (),
+        }
+
         ui_frame.render(&mut ctx);
         graphics::present(&mut ctx);
 
@@ -194,5 +381,9 @@ fn main() -> Result<()> {
         timer::yield_now();
     }
 
+    if let Some(path) = &opts.record {
+        command::Recording::finish(&mut world, path)?;
+    }
+
     Ok(())
 }
\ No newline at end of file
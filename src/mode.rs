@@ -1,11 +1,9 @@
-use ggez::{
-    event,
-    Context,
-};
+use ggez::Context;
 use imgui::Ui;
 use specs::prelude::*;
 
 use crate::draw::ModeText;
+use crate::input::Input;
 
 pub enum TopAction {
     Do(EventAction),
@@ -38,10 +36,10 @@ pub trait Mode {
     fn on_pop(&mut self, _world: &mut World) { }
     fn on_show(&mut self, _world: &mut World) { }
     fn on_hide(&mut self, _world: &mut World) { }
-    fn on_event(&mut self, _world: &mut World, _ctx: &mut Context, _event: event::Event) -> EventAction {
+    fn on_event(&mut self, _world: &mut World, _ctx: &mut Context, _input: Input) -> EventAction {
         EventAction::Continue
     }
-    fn on_top_event(&mut self, _world: &mut World, _ctx: &mut Context, _event: event::Event) -> TopAction {
+    fn on_top_event(&mut self, _world: &mut World, _ctx: &mut Context, _input: Input) -> TopAction {
         TopAction::AsEvent
     }
     fn on_ui(&mut self, _world: &mut World, _ui: &Ui) -> EventAction {
@@ -56,13 +54,13 @@ pub struct Stack(Vec<Box<Mode>>);
 
 impl Stack {
     pub fn new() -> Self { Stack(vec![]) }
-    fn top_mut(&mut self) -> Option<&mut Box<Mode>> {
+    fn top_mut(&mut self) -> Option<&mut Mode> {
         if self.0.is_empty() { return None }
         let ix = self.0.len()-1;
-        Some(&mut self.0[ix])
+        Some(&mut *self.0[ix])
     }
     pub fn push(&mut self, world: &mut World, mut mode: Box<Mode>) {
-        self.top_mut().map(|mode| mode.on_hide(world));
+        if let Some(top) = self.top_mut() { top.on_hide(world); }
         mode.on_push(world);
         mode.on_show(world);
         world.write_resource::<ModeText>().set(mode.name());
@@ -85,11 +83,11 @@ impl Stack {
             }
         }
     }
-    pub fn handle_event(&mut self, world: &mut World, ctx: &mut Context, event: event::Event) {
+    pub fn handle_input(&mut self, world: &mut World, ctx: &mut Context, input: Input) {
         self.apply(
             world, ctx,
-            |mode, world, ctx| { mode.on_top_event(world, ctx, event.clone()) },
-            |mode, world, ctx| { mode.on_event(world, ctx, event.clone()) },
+            |mode, world, ctx| { mode.on_top_event(world, ctx, input) },
+            |mode, world, ctx| { mode.on_event(world, ctx, input) },
         )
     }
     pub fn handle_ui(&mut self, world: &mut World, mut ui: &Ui) {
@@ -131,7 +129,7 @@ impl Stack {
                 EventAction::Push(act) => { self.push(world, act); return },
             }
             if ix == 0 { break }
-            ix = ix - 1;
+            ix -= 1;
         }
 
     }
@@ -1,28 +1,62 @@
 use std::{
     any::TypeId,
-    collections::{HashMap, VecDeque},
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque},
 };
 
-use hibitset::BitSet;
 use petgraph::{self, graphmap::GraphMap};
+use serde_derive::{Deserialize, Serialize};
 use specs::{
     prelude::*,
     storage::BTreeStorage,
 };
 
-use crate::error::or_die;
+use crate::build;
+use crate::defs::{Defs, GridDef};
+use crate::error::{Error, Result, or_die};
 use crate::geom;
 use crate::graph;
+use crate::reactor;
+use crate::save::{Loader, Persist, Saver};
 use crate::util::try_get;
 
+/// When power runs short, each class is served in full before any of the
+/// next lower one is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub fn all() -> [Priority; 3] { [Priority::Low, Priority::Normal, Priority::High] }
+}
+
 #[derive(Debug)]
 pub struct Power {
-    has: HashMap<TypeId, f32>,
+    // Ordered, so that totals are summed the same way every time.
+    has: BTreeMap<TypeId, f32>,
     from_grid: f32,
+    /// Only matters while the node is drawing power.
+    pub priority: Priority,
+    /// Lost in transmission, on top of `from_grid`, to get power here.
+    lost: f32,
 }
 
 impl Power {
-    pub fn new() -> Self { Power { has: HashMap::new(), from_grid: 0.0 } }
+    pub fn new() -> Self {
+        Power { has: BTreeMap::new(), from_grid: 0.0, priority: Priority::Normal, lost: 0.0 }
+    }
+    /// Gives a node a share of the grid, if it hasn't one already.
+    pub fn add(world: &mut World, entity: Entity) {
+        or_die(|| {
+            let mut powers = world.write_storage::<Power>();
+            if powers.get(entity).is_none() { powers.insert(entity, Power::new())?; }
+            world.write_resource::<PowerGrid>().add_power(entity);
+            Ok(())
+        });
+    }
     pub fn set<T: 'static>(&mut self, amount: f32) -> Option<f32> {
         self.has.insert(TypeId::of::<T>(), amount)
     }
@@ -32,16 +66,15 @@ impl Power {
     pub fn total(&self) -> f32 {
         self.has.values().sum()
     }
-    pub fn from_grid(&self) -> f32 { self.from_grid }
+    pub fn grid(&self) -> f32 { self.from_grid }
+    pub fn lost(&self) -> f32 { self.lost }
     pub fn ratio(&self) -> f32 {
         let total = self.total();
         if total == 0.0 { 1.0 }
         else { self.from_grid / self.total() }
     }
-    pub fn uses(&self) -> impl Iterator<Item=f32> {
-        let mut items: Vec<(TypeId, f32)> = self.has.iter().map(|(&k, &v)| (k, v)).collect();
-        items.sort_by_key(|(k, _)| *k);
-        items.into_iter().map(|(_, v)| v)
+    pub fn uses<'a>(&'a self) -> impl Iterator<Item=f32> + 'a {
+        self.has.values().cloned()
     }
 }
 
@@ -49,51 +82,344 @@ impl Component for Power {
     type Storage = BTreeStorage<Self>;
 }
 
+// Power is keyed by the type of whatever is using it, which can't be saved
+// directly; these are all the users that can end up in a save file.
+fn user_names() -> Vec<(TypeId, &'static str)> {
+    vec![
+        (TypeId::of::<()>(), "base"),
+        (TypeId::of::<reactor::RunReactors>(), "reactor"),
+        (TypeId::of::<build::Production>(), "production"),
+    ]
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PowerData {
+    has: Vec<(String, f32)>,
+    from_grid: f32,
+    priority: Priority,
+    lost: f32,
+}
+
+impl Persist for Power {
+    type Data = PowerData;
+    fn save(&self, _: &Saver) -> Result<PowerData> {
+        let names = user_names();
+        let mut has = vec![];
+        for (id, &amount) in &self.has {
+            let name = names.iter().find(|(n_id, _)| n_id == id)
+                .ok_or_else(|| Error::UnknownPowerUser(format!("{:?}", id)))?.1;
+            has.push((name.to_owned(), amount));
+        }
+        Ok(PowerData { has, from_grid: self.from_grid, priority: self.priority, lost: self.lost })
+    }
+    fn load(data: PowerData, _: &Loader) -> Result<Self> {
+        let names = user_names();
+        let mut has = BTreeMap::new();
+        for (name, amount) in data.has {
+            let id = names.iter().find(|(_, n)| *n == name)
+                .ok_or_else(|| Error::UnknownPowerUser(name.clone()))?.0;
+            has.insert(id, amount);
+        }
+        Ok(Power { has, from_grid: data.from_grid, priority: data.priority, lost: data.lost })
+    }
+}
+
+/// A connected set of pylons, and the nodes they cover.
+#[derive(Debug, Default)]
+struct Network {
+    pylons: BTreeSet<Entity>,
+    /// How many of the network's pylons cover each node.
+    cover: HashMap<Entity, u32>,
+    /// The covered nodes with a `Power` or a `Battery`; the only ones
+    /// distribution has to look at.
+    powers: BTreeSet<Entity>,
+    batteries: BTreeSet<Entity>,
+    losses: Option<Losses>,
+}
+
+/// The fraction of power lost getting from the nearest supply to each pylon
+/// and covered node.  Worked out again whenever the suppliers change.
+#[derive(Debug)]
+struct Losses {
+    suppliers: Vec<Entity>,
+    pylons: HashMap<Entity, f32>,
+    nodes: HashMap<Entity, f32>,
+}
+
+impl Losses {
+    fn efficiency(&self, node: Entity) -> f32 {
+        fmax(0.0, 1.0 - self.nodes.get(&node).cloned().unwrap_or(1.0))
+    }
+}
+
+// Losses are summed as integers so that paths of equal loss come out exactly
+// equal, whichever order they're found in.
+const LOSS_UNITS: f32 = 1_000_000.0;
+
+/// Every node with a `Power` or a `Battery`, wherever it is, so networks
+/// know which of the nodes they cover to keep track of.
+#[derive(Debug, Default)]
+struct Users {
+    powers: HashSet<Entity>,
+    batteries: HashSet<Entity>,
+}
+
+impl Network {
+    fn cover(&mut self, node: Entity, users: &Users) {
+        self.losses = None;
+        *self.cover.entry(node).or_insert(0) += 1;
+        if users.powers.contains(&node) { self.powers.insert(node); }
+        if users.batteries.contains(&node) { self.batteries.insert(node); }
+    }
+    fn uncover(&mut self, node: Entity) {
+        self.losses = None;
+        let left = match self.cover.get_mut(&node) {
+            Some(count) => { *count -= 1; *count },
+            None => return,
+        };
+        if left == 0 {
+            self.cover.remove(&node);
+            self.powers.remove(&node);
+            self.batteries.remove(&node);
+        }
+    }
+    fn absorb(&mut self, other: Network) {
+        self.losses = None;
+        self.pylons.extend(other.pylons);
+        for (node, count) in other.cover {
+            *self.cover.entry(node).or_insert(0) += count;
+        }
+        self.powers.extend(other.powers);
+        self.batteries.extend(other.batteries);
+    }
+}
+
+/// Networks are kept up to date as pylons and the nodes they cover come and
+/// go, so distributing power each tick doesn't have to search the grid.
+/// Joining networks is cheap; a network that loses a pylon or a link is
+/// searched again, since it may have split.
 pub struct PowerGrid {
     graph: GraphMap<Entity, (), petgraph::Undirected>,
+    // Keyed by lowest pylon, so networks are powered in the same order
+    // however they came to be.
+    networks: BTreeMap<Entity, Network>,
+    network_of: HashMap<Entity, Entity>,
+    users: Users,
 }
 
 impl PowerGrid {
     pub fn new() -> Self {
         PowerGrid {
             graph: GraphMap::new(),
+            networks: BTreeMap::new(),
+            network_of: HashMap::new(),
+            users: Users::default(),
         }
     }
-    fn add_link(&mut self, from: Entity, to: Entity) {
+    /// Adds a pylon linked to `links` and covering `nodes`.
+    fn add<L, N>(&mut self, pylon: Entity, links: L, nodes: N)
+        where L: IntoIterator<Item=Entity>, N: IntoIterator<Item=Entity>,
+    {
+        self.graph.add_node(pylon);
+        let mut joined = Network::default();
+        joined.pylons.insert(pylon);
+        for node in nodes { joined.cover(node, &self.users); }
+        for other in links {
+            self.graph.add_edge(pylon, other, ());
+            if let Some(key) = self.network_of.get(&other).cloned() {
+                if let Some(net) = self.networks.remove(&key) { joined.absorb(net); }
+            }
+        }
+        self.insert(joined);
+    }
+    /// Links two pylons by hand.
+    pub fn link(&mut self, from: Entity, to: Entity) {
         self.graph.add_edge(from, to, ());
+        let from_key = if let Some(&k) = self.network_of.get(&from) { k } else { return };
+        let to_key = if let Some(&k) = self.network_of.get(&to) { k } else { return };
+        if from_key == to_key { return }
+        let mut joined = if let Some(n) = self.networks.remove(&from_key) { n } else { return };
+        if let Some(net) = self.networks.remove(&to_key) { joined.absorb(net); }
+        self.insert(joined);
+    }
+    /// Cuts the link between two pylons, which may leave their network in two.
+    pub fn unlink(&mut self, from: Entity, to: Entity, areas: &ReadStorage<geom::AreaSet>) {
+        if self.graph.remove_edge(from, to).is_none() { return }
+        let key = if let Some(&k) = self.network_of.get(&from) { k } else { return };
+        if let Some(net) = self.networks.remove(&key) { self.split(net.pylons, areas); }
+    }
+    pub fn is_linked(&self, from: Entity, to: Entity) -> bool { self.graph.contains_edge(from, to) }
+    fn insert(&mut self, net: Network) {
+        let key = *net.pylons.iter().next().unwrap();
+        for &p in &net.pylons { self.network_of.insert(p, key); }
+        self.networks.insert(key, net);
+    }
+    /// Takes a demolished node off the grid, whether it's a pylon or not.
+    pub fn remove(&mut self, pylon: Entity, areas: &ReadStorage<geom::AreaSet>) {
+        self.users.powers.remove(&pylon);
+        self.users.batteries.remove(&pylon);
+        self.graph.remove_node(pylon);
+        let key = if let Some(k) = self.network_of.remove(&pylon) { k } else { return };
+        if let Some(mut net) = self.networks.remove(&key) {
+            net.pylons.remove(&pylon);
+            self.split(net.pylons, areas);
+        }
+    }
+    /// Re-forms networks from scratch over the given pylons.
+    fn split(&mut self, mut pylons: BTreeSet<Entity>, areas: &ReadStorage<geom::AreaSet>) {
+        while let Some(start) = pylons.iter().next().cloned() {
+            pylons.remove(&start);
+            let mut net = Network::default();
+            let mut pending = VecDeque::new();
+            pending.push_back(start);
+            net.pylons.insert(start);
+            while let Some(pylon) = pending.pop_front() {
+                for n in self.graph.neighbors(pylon) {
+                    if pylons.remove(&n) {
+                        net.pylons.insert(n);
+                        pending.push_back(n);
+                    }
+                }
+                let area = if let Some(a) = areas.get(pylon) { a } else { continue };
+                for entity in area.nodes() { net.cover(entity, &self.users); }
+            }
+            self.insert(net);
+        }
+    }
+    /// Rebuilds every network, after loading.
+    pub fn rebuild(
+        &mut self, entities: &Entities, pylons: &ReadStorage<Pylon>, areas: &ReadStorage<geom::AreaSet>,
+        powers: &ReadStorage<Power>, batteries: &ReadStorage<Battery>,
+    ) {
+        self.networks.clear();
+        self.network_of.clear();
+        self.users.powers = (&**entities, powers).join().map(|(e, _)| e).collect();
+        self.users.batteries = (&**entities, batteries).join().map(|(e, _)| e).collect();
+        let mut all = BTreeSet::new();
+        for (pylon, _) in (&**entities, pylons).join() {
+            self.graph.add_node(pylon);
+            all.insert(pylon);
+        }
+        self.split(all, areas);
+    }
+    /// A node has come into range of a pylon.
+    pub fn cover(&mut self, pylon: Entity, node: Entity) {
+        let key = if let Some(&k) = self.network_of.get(&pylon) { k } else { return };
+        if let Some(net) = self.networks.get_mut(&key) { net.cover(node, &self.users); }
+    }
+    /// A node now has a `Power`, and any network covering it should see to it.
+    fn add_power(&mut self, node: Entity) {
+        if !self.users.powers.insert(node) { return }
+        for net in self.networks.values_mut() {
+            if net.cover.contains_key(&node) { net.powers.insert(node); }
+        }
+    }
+    fn add_battery(&mut self, node: Entity) {
+        if !self.users.batteries.insert(node) { return }
+        for net in self.networks.values_mut() {
+            if net.cover.contains_key(&node) { net.batteries.insert(node); }
+        }
+    }
+    /// A node within range of a pylon is gone.
+    pub fn uncover(&mut self, pylon: Entity, node: Entity) {
+        let key = if let Some(&k) = self.network_of.get(&pylon) { k } else { return };
+        if let Some(net) = self.networks.get_mut(&key) { net.uncover(node); }
     }
-    fn find_covered(
-        &self, areas: &ReadStorage<geom::AreaSet>,
-        start: Entity, visited: &mut BitSet,
-    ) -> BitSet {
-        let mut pending = VecDeque::new();
-        pending.push_back(start);
-        visited.add(start.id());
-        let mut covered = BitSet::new();
-        while !pending.is_empty() {
-            let pylon = pending.pop_front().unwrap();
-            for n in self.graph.neighbors(pylon) {
-                if !visited.add(n.id()) { pending.push_back(n) }
+    /// Networks by key, in a fixed order.
+    fn keys(&self) -> Vec<Entity> { self.networks.keys().cloned().collect() }
+    /// Brings a network's losses up to date for the given suppliers.
+    fn refresh_losses(
+        &mut self, key: Entity, suppliers: Vec<Entity>, model: &GridDef,
+        nodes: &ReadStorage<graph::Node>, areas: &ReadStorage<geom::AreaSet>,
+    ) {
+        let graph = &self.graph;
+        let net = if let Some(n) = self.networks.get_mut(&key) { n } else { return };
+        if net.losses.as_ref().map_or(false, |l| l.suppliers == suppliers) { return }
+        let is_supplier: HashSet<Entity> = suppliers.iter().cloned().collect();
+        let mut best = HashMap::<Entity, u64>::new();
+        let mut pending = BinaryHeap::new();
+        for &pylon in &net.pylons {
+            let area = if let Some(a) = areas.get(pylon) { a } else { continue };
+            if area.nodes().any(|n| is_supplier.contains(&n)) {
+                best.insert(pylon, 0);
+                pending.push(Reverse((0u64, pylon)));
+            }
+        }
+        while let Some(Reverse((cost, pylon))) = pending.pop() {
+            if best.get(&pylon).map_or(false, |&b| b < cost) { continue }
+            let at = if let Some(n) = nodes.get(pylon) { n.at() } else { continue };
+            for other in graph.neighbors(pylon) {
+                let other_at = if let Some(n) = nodes.get(other) { n.at() } else { continue };
+                let hop = model.loss_per_hop + model.loss_per_hex * (at.distance(other_at) as f32);
+                let next = cost + (hop * LOSS_UNITS).round() as u64;
+                if best.get(&other).map_or(true, |&b| next < b) {
+                    best.insert(other, next);
+                    pending.push(Reverse((next, other)));
+                }
             }
+        }
+        let mut losses = Losses { suppliers, pylons: HashMap::new(), nodes: HashMap::new() };
+        for (&pylon, &cost) in &best {
+            let loss = fmin(1.0, (cost as f32) / LOSS_UNITS);
+            losses.pylons.insert(pylon, loss);
             let area = if let Some(a) = areas.get(pylon) { a } else { continue };
-            for entity in area.nodes() { covered.add(entity.id()); }
+            for node in area.nodes() {
+                let l = losses.nodes.entry(node).or_insert(1.0);
+                *l = fmin(*l, loss);
+            }
         }
-        covered
+        net.losses = Some(losses);
+    }
+    /// The fraction of power lost reaching this pylon, if it's on a powered
+    /// network.
+    pub fn loss_at(&self, pylon: Entity) -> Option<f32> {
+        let net = self.networks.get(self.network_of.get(&pylon)?)?;
+        net.losses.as_ref()?.pylons.get(&pylon).cloned()
     }
     pub fn links<'a>(&'a self, from: Entity) -> impl Iterator<Item=Entity> + 'a {
         self.graph.neighbors(from)
     }
+    pub fn save(&self, saver: &Saver) -> Result<Vec<(u32, u32)>> {
+        let mut out = vec![];
+        for (from, to, _) in self.graph.all_edges() {
+            out.push((saver.id(from)?, saver.id(to)?));
+        }
+        Ok(out)
+    }
+    /// Networks aren't saved; `rebuild` them once the rest is loaded.
+    pub fn load(data: Vec<(u32, u32)>, loader: &Loader) -> Result<Self> {
+        let mut grid = PowerGrid::new();
+        for (from, to) in data {
+            grid.graph.add_edge(loader.entity(from)?, loader.entity(to)?, ());
+        }
+        Ok(grid)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Pylon {
     range: i32,
+    /// Isolated pylons only get links made by hand, so that new pylons
+    /// nearby don't join a sub-grid that was deliberately cut off.
+    pub isolated: bool,
 }
 
 impl Pylon {
     pub fn range(&self) -> i32 { self.range }
 }
 
+/// Pylons can be linked when their areas overlap.
+pub fn can_link(world: &World, from: Entity, to: Entity) -> bool {
+    if from == to || world.read_resource::<PowerGrid>().is_linked(from, to) { return false }
+    let pylons = world.read_storage::<Pylon>();
+    let nodes = world.read_storage::<graph::Node>();
+    match (pylons.get(from), pylons.get(to), nodes.get(from), nodes.get(to)) {
+        (Some(fp), Some(tp), Some(fnode), Some(tnode)) =>
+            fnode.at().distance(tnode.at()) <= fp.range + tp.range,
+        _ => false,
+    }
+}
+
 impl Component for Pylon {
     type Storage = BTreeStorage<Self>;
 }
@@ -102,20 +428,52 @@ impl Pylon {
     pub fn add(world: &mut World, entity: Entity, range: i32) {
         or_die(|| {
             let at = try_get(&world.read_storage::<graph::Node>(), entity)?.at();
-            {
+            let links: Vec<Entity> = {
                 let map = world.read_resource::<geom::AreaMap>();
                 let pylons = world.read_storage::<Pylon>();
                 let found = map.find_overlap(at, range) & pylons.mask();
-                let mut grid = world.write_resource::<PowerGrid>();
-                for (other, _) in (&*world.entities(), found).join() {
-                    grid.add_link(entity, other);
-                }
-            }
+                (&*world.entities(), &pylons, found).join()
+                    .filter(|(_, pylon, _)| !pylon.isolated)
+                    .map(|(other, _, _)| other)
+                    .collect()
+            };
             geom::AreaSet::add(world, entity, range)?;
-            world.write_storage().insert(entity, Pylon { range })?;
+            world.write_storage().insert(entity, Pylon { range, isolated: false })?;
+            let nodes: Vec<Entity> = try_get(&world.read_storage::<geom::AreaSet>(), entity)?.nodes().collect();
+            world.write_resource::<PowerGrid>().add(entity, links, nodes);
+            Ok(())
+        });
+    }
+}
+
+/// Soaks up surplus power on its network and gives it back when there's a
+/// shortfall.  Charge is in kJ, rate in kJ/s.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Battery {
+    capacity: f32,
+    rate: f32,
+    charge: f32,
+    /// Positive while discharging, negative while charging.
+    flow: f32,
+}
+
+impl Battery {
+    pub fn add(world: &mut World, entity: Entity, capacity: f32, rate: f32) {
+        or_die(|| {
+            world.write_storage().insert(entity, Battery { capacity, rate, charge: 0.0, flow: 0.0 })?;
+            world.write_resource::<PowerGrid>().add_battery(entity);
             Ok(())
         });
     }
+    pub fn charge(&self) -> f32 { self.charge }
+    pub fn capacity(&self) -> f32 { self.capacity }
+    pub fn flow(&self) -> f32 { self.flow }
+    fn can_charge(&self) -> f32 { fmin(self.rate, (self.capacity - self.charge) / super::UPDATE_DELTA) }
+    fn can_discharge(&self) -> f32 { fmin(self.rate, self.charge / super::UPDATE_DELTA) }
+}
+
+impl Component for Battery {
+    type Storage = BTreeStorage<Self>;
 }
 
 #[derive(Debug)]
@@ -124,38 +482,108 @@ pub struct DistributePower;
 #[derive(shred_derive::SystemData)]
 pub struct DistributePowerData<'a> {
     entities: Entities<'a>,
-    grid: ReadExpect<'a, PowerGrid>,
+    defs: ReadExpect<'a, Defs>,
+    grid: WriteExpect<'a, PowerGrid>,
+    nodes: ReadStorage<'a, graph::Node>,
     areas: ReadStorage<'a, geom::AreaSet>,
-    pylons: ReadStorage<'a, Pylon>,
     powers: WriteStorage<'a, Power>,
+    batteries: WriteStorage<'a, Battery>,
 }
 
 impl<'a> System<'a> for DistributePower {
     type SystemData = DistributePowerData<'a>;
 
     fn run(&mut self, mut data: Self::SystemData) {
-        let mut marked = BitSet::new();
-        for (pylon, _) in (&*data.entities, &data.pylons).join() {
-            if marked.contains(pylon.id()) { continue }
-            let covered = data.grid.find_covered(&data.areas, pylon, &mut marked);
+        let was_charging: HashSet<Entity> = (&data.entities, &data.batteries).join()
+            .filter(|(_, b)| b.flow < 0.0)
+            .map(|(e, _)| e)
+            .collect();
+        // Batteries off the grid sit idle.
+        for battery in (&mut data.batteries).join() { battery.flow = 0.0; }
+        for key in data.grid.keys() {
+            let suppliers: Vec<Entity> = {
+                let net = &data.grid.networks[&key];
+                let (powers, batteries) = (&data.powers, &data.batteries);
+                // A battery that was charging isn't a supply, so what it
+                // draws pays for the distance from the nearest one.
+                net.powers.union(&net.batteries).cloned()
+                    .filter(|&entity| {
+                        powers.get(entity).map_or(false, |p| p.total() > 0.0)
+                            || (batteries.get(entity).map_or(false, |b| b.charge > 0.0)
+                                && !was_charging.contains(&entity))
+                    })
+                    .collect()
+            };
+            data.grid.refresh_losses(key, suppliers, data.defs.grid(), &data.nodes, &data.areas);
+            let net = &data.grid.networks[&key];
+            let losses = match &net.losses { Some(l) => l, None => continue };
+
+            // Demand is what consumers need plus what's lost getting it to
+            // them; a consumer out of reach of any supply gets nothing.
             let mut supply = 0.0;
-            let mut demand = 0.0;
-            for (power, _) in (&data.powers, &covered).join() {
+            let mut demand = BTreeMap::<Priority, f32>::new();
+            for &entity in &net.powers {
+                let power = if let Some(p) = data.powers.get(entity) { p } else { continue };
                 let total = power.total();
                 if total >= 0.0 {
                     supply += total
                 } else {
-                    demand += total.abs()
+                    let efficiency = losses.efficiency(entity);
+                    if efficiency > 0.0 {
+                        *demand.entry(power.priority).or_insert(0.0) += total.abs() / efficiency
+                    }
                 }
             }
-            let will_supply = fmin(supply, demand);
-            let (in_scale, out_scale) = if demand > 0.0 && supply > 0.0 {
-                (will_supply / demand, will_supply / supply)
-            } else { (0.0, 0.0) };
-            for (power, _) in (&mut data.powers, covered).join() {
+            let total_demand: f32 = demand.values().sum();
+            // Batteries make up the difference, as far as they're able.
+            // Charging draws what's lost on the way as well; discharging
+            // doesn't, as consumers already pay for their distance from
+            // the nearest supply.
+            let mut can_charge = 0.0;
+            let mut can_discharge = 0.0;
+            for &entity in &net.batteries {
+                let battery = if let Some(b) = data.batteries.get(entity) { b } else { continue };
+                let efficiency = losses.efficiency(entity);
+                if efficiency > 0.0 { can_charge += battery.can_charge() / efficiency; }
+                can_discharge += battery.can_discharge();
+            }
+            let (used, mut available, charge_scale, discharge_scale) = if supply > total_demand {
+                let charging = fmin(supply - total_demand, can_charge);
+                let scale = if can_charge > 0.0 { charging / can_charge } else { 0.0 };
+                (total_demand + charging, total_demand, scale, 0.0)
+            } else {
+                let discharging = fmin(total_demand - supply, can_discharge);
+                let scale = if can_discharge > 0.0 { discharging / can_discharge } else { 0.0 };
+                (supply, supply + discharging, 0.0, scale)
+            };
+            for &entity in &net.batteries {
+                let battery = if let Some(b) = data.batteries.get_mut(entity) { b } else { continue };
+                let charging = if losses.efficiency(entity) > 0.0 {
+                    battery.can_charge() * charge_scale
+                } else { 0.0 };
+                battery.flow = battery.can_discharge() * discharge_scale - charging;
+                battery.charge -= battery.flow * super::UPDATE_DELTA;
+                battery.charge = fmax(0.0, fmin(battery.capacity, battery.charge));
+            }
+            let out_scale = if supply > 0.0 { used / supply } else { 0.0 };
+            let mut in_scales = BTreeMap::new();
+            for (&priority, &wanted) in demand.iter().rev() {
+                let served = fmin(available, wanted);
+                available -= served;
+                in_scales.insert(priority, served / wanted);
+            }
+            for &entity in &net.powers {
+                let power = if let Some(p) = data.powers.get_mut(entity) { p } else { continue };
                 let total = power.total();
+                power.lost = 0.0;
                 if total < 0.0 {
-                    power.from_grid = total * in_scale
+                    let efficiency = losses.efficiency(entity);
+                    if efficiency > 0.0 {
+                        power.from_grid = total * in_scales[&power.priority];
+                        power.lost = power.from_grid.abs() * (1.0 / efficiency - 1.0);
+                    } else {
+                        power.from_grid = 0.0
+                    }
                 } else {
                     power.from_grid = total * out_scale
                 }
@@ -169,7 +597,88 @@ fn fmin(a: f32, b: f32) -> f32 {
     if a > b { b } else { a }
 }
 
-#[allow(unused)]
 fn fmax(a: f32, b: f32) -> f32 {
     if a > b { a } else { b }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex2d::Coordinate;
+
+    fn make(world: &mut World, name: &str, x: i32, y: i32) -> Entity {
+        let node = graph::make_node(world, Coordinate { x, y });
+        let kind = world.read_resource::<Defs>().find(name).unwrap();
+        kind.make(world, node);
+        node
+    }
+
+    fn network_of(world: &World, pylon: Entity) -> Entity {
+        world.read_resource::<PowerGrid>().network_of[&pylon]
+    }
+
+    #[test]
+    fn battery_tracked_once_covered() {
+        let mut world = crate::test_world();
+        let mut update = crate::make_update();
+        let seed = (&*world.entities(), &world.read_storage::<Pylon>()).join().next().unwrap().0;
+        let battery = make(&mut world, "Battery", 3, 0);
+        {
+            let grid = world.read_resource::<PowerGrid>();
+            let net = &grid.networks[&network_of(&world, seed)];
+            assert!(net.batteries.contains(&battery));
+            assert!(net.powers.contains(&seed));
+        }
+        for _ in 0..10 { crate::step(&mut world, &mut update); }
+        assert!(world.read_storage::<Battery>().get(battery).unwrap().charge() > 0.0);
+
+        crate::demolish::node(&mut world, battery);
+        let grid = world.read_resource::<PowerGrid>();
+        assert!(grid.networks.values().all(|net| !net.batteries.contains(&battery)));
+        assert!(!grid.users.batteries.contains(&battery));
+    }
+
+    #[test]
+    fn battery_charging_pays_losses() {
+        let mut world = crate::test_world();
+        let mut update = crate::make_update();
+        make(&mut world, "Pylon", 30, 0);
+        // Only in range of the far pylon, one hop of 30 hexes away.
+        let battery = make(&mut world, "Battery", 45, 0);
+        for step in 1..=3 {
+            crate::step(&mut world, &mut update);
+            let charge = world.read_storage::<Battery>().get(battery).unwrap().charge();
+            let expected = 100.0 * 0.95 * crate::UPDATE_DELTA * (step as f32);
+            assert!((charge - expected).abs() < 1e-2, "{} after step {}, not {}", charge, step, expected);
+        }
+    }
+
+    #[test]
+    fn losses_and_splits() {
+        let mut world = crate::test_world();
+        let mut update = crate::make_update();
+        let seed = (&*world.entities(), &world.read_storage::<Pylon>()).join().next().unwrap().0;
+        let pylon = make(&mut world, "Pylon", 30, 0);
+        assert_eq!(network_of(&world, pylon), network_of(&world, seed));
+        // Only in range of the far pylon.
+        let consumer = graph::make_node(&mut world, Coordinate { x: 45, y: 0 });
+        Power::add(&mut world, consumer);
+        world.write_storage::<Power>().get_mut(consumer).unwrap().set::<()>(-10.0);
+        crate::step(&mut world, &mut update);
+
+        // One hop of 30 hexes.
+        let loss = world.read_resource::<PowerGrid>().loss_at(pylon).unwrap();
+        assert!((loss - 0.05).abs() < 1e-4);
+        {
+            let powers = world.read_storage::<Power>();
+            let power = powers.get(consumer).unwrap();
+            assert!((power.grid() + 10.0).abs() < 1e-4);
+            assert!((power.lost() - 10.0 * (1.0 / 0.95 - 1.0)).abs() < 1e-3);
+        }
+
+        world.write_resource::<PowerGrid>().unlink(seed, pylon, &world.read_storage());
+        assert_ne!(network_of(&world, pylon), network_of(&world, seed));
+        crate::step(&mut world, &mut update);
+        assert_eq!(world.read_resource::<PowerGrid>().loss_at(pylon), None);
+        assert!(world.read_storage::<Power>().get(consumer).unwrap().grid().abs() < 1e-4);
+    }
+}
@@ -1,25 +1,28 @@
 use std::time::Duration;
 
-use hibitset::BitSet;
-use rand::{self, Rng};
+use hibitset::{BitSet, BitSetLike};
+use rand::Rng;
+use serde_derive::{Deserialize, Serialize};
 use specs::{
     prelude::*,
     storage::BTreeStorage,
 };
 
-use crate::error::or_die;
+use crate::defs::ReactorDef;
+use crate::error::{Result, or_die};
 use crate::geom;
 use crate::graph;
 use crate::power::Power;
 use crate::resource::{self, Pool, Resource, Sink, Source};
+use crate::save::{Loader, Persist, Saver};
 use crate::util::{duration_f32, f32_duration};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Progress {
     made: Option<ActiveProgress>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct ActiveProgress {
     at: Duration,
     target: Duration,
@@ -76,34 +79,38 @@ pub struct Reactor {
     delay: Duration,
     output: Pool,
     power_per_second: f32,
+    plant: bool,
     targets: BitSet,
 }
 
 impl Reactor {
-    pub fn add(
-        world: &mut World, entity: Entity,
-        input: Pool, delay: Duration, output: Pool, total_power: f32, range: i32,
-    ) {
-        Source::add(world, entity, Pool::new(), range);
+    pub fn add(world: &mut World, entity: Entity, def: &ReactorDef) {
+        let recipe = &def.recipe;
+        Source::add(world, entity, Pool::new(), def.range);
         or_die(|| {
             let mut sink = Sink::new();
-            sink.want = input.clone();
+            sink.want = recipe.input.clone();
             world.write_storage().insert(entity, sink)?;
             
-            world.write_storage().insert(entity, Power::new())?;
+            Power::add(world, entity);
             world.write_storage().insert(entity, Progress::new())?;
-            let power_per_second = total_power / duration_f32(delay);
+            let power_per_second = recipe.power / duration_f32(recipe.delay);
             let mut targets = BitSet::new();
-            for (r, _) in output.iter() { targets.add(r as u32); }
+            for (r, _) in recipe.output.iter() { targets.add(r.index() as u32); }
             world.write_storage().insert(entity, Reactor {
-                input, delay, output, power_per_second, targets,
+                input: recipe.input.clone(),
+                delay: recipe.delay,
+                output: recipe.output.clone(),
+                power_per_second,
+                plant: def.plant,
+                targets,
             })?;
             Ok(())
         });
     }
     pub fn input(&self) -> &Pool { &self.input }
+    pub fn is_plant(&self) -> bool { self.plant }
     pub fn output(&self) -> &Pool { &self.output }
-    #[allow(unused)]
     pub fn targets(&self) -> &BitSet { &self.targets }
     pub fn targets_mut(&mut self) -> &mut BitSet { &mut self.targets }
 }
@@ -112,7 +119,43 @@ impl Component for Reactor {
     type Storage = BTreeStorage<Self>;
 }
 
-#[derive(Debug, Default)]
+#[derive(Serialize, Deserialize)]
+pub struct ReactorData {
+    input: Pool,
+    delay: Duration,
+    output: Pool,
+    power_per_second: f32,
+    plant: bool,
+    targets: Vec<u32>,
+}
+
+impl Persist for Reactor {
+    type Data = ReactorData;
+    fn save(&self, _: &Saver) -> Result<ReactorData> {
+        Ok(ReactorData {
+            input: self.input.clone(),
+            delay: self.delay,
+            output: self.output.clone(),
+            power_per_second: self.power_per_second,
+            plant: self.plant,
+            targets: (&self.targets).iter().collect(),
+        })
+    }
+    fn load(data: ReactorData, _: &Loader) -> Result<Self> {
+        let mut targets = BitSet::new();
+        for t in data.targets { targets.add(t); }
+        Ok(Reactor {
+            input: data.input,
+            delay: data.delay,
+            output: data.output,
+            power_per_second: data.power_per_second,
+            plant: data.plant,
+            targets,
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Waste;
 
 impl Component for Waste {
@@ -122,16 +165,18 @@ impl Component for Waste {
 #[derive(Debug)]
 pub struct RunReactors;
 
+type RunReactorsData<'a> = (
+    ReadStorage<'a, graph::Node>,
+    WriteStorage<'a, Reactor>,
+    WriteStorage<'a, Progress>,
+    WriteStorage<'a, Source>,
+    WriteStorage<'a, Sink>,
+    WriteStorage<'a, Power>,
+    Write<'a, LazyUpdate>,
+);
+
 impl<'a> System<'a> for RunReactors {
-    type SystemData = (
-        ReadStorage<'a, graph::Node>,
-        WriteStorage<'a, Reactor>,
-        WriteStorage<'a, Progress>,
-        WriteStorage<'a, Source>,
-        WriteStorage<'a, Sink>,
-        WriteStorage<'a, Power>,
-        Read<'a, LazyUpdate>,
-    );
+    type SystemData = RunReactorsData<'a>;
 
     fn run(&mut self, (nodes, mut reactors, mut progs, mut sources, mut sinks, mut powers, lazy): Self::SystemData) {
         for (node, reactor, progress, source, sink, power) in (&nodes, &mut reactors, &mut progs, &mut sources, &mut sinks, &mut powers).join() {
@@ -150,17 +195,22 @@ impl<'a> System<'a> for RunReactors {
             if progress.made.is_some() { continue }
             let has_input = reactor.input.iter().all(|(r, c)| sink.has.get(r) >= c);
             if !has_input { continue }
+            // Power plants run whenever the grid wants power; anything they
+            // make besides is a byproduct, and spills as waste if unwanted.
             // TODO: make output gating controllable
-            let needs_output = {
+            let needs_output = reactor.plant || {
                 let targets = &reactor.targets;
                 reactor.output.iter().any(|(r, c)| {
-                    targets.contains(r as u32) && source.has.get(r) < c
+                    targets.contains(r.index() as u32) && source.has.get(r) < c
                 })
             };
             if !needs_output { continue }
             // Start requesting power, and only continue if we're getting any.
+            // A power plant likewise waits until something on the grid draws
+            // on it; other reactions that happen to give off power just run.
             power.set::<Self>(reactor.power_per_second);
-            if power.ratio() == 0.0 { continue }
+            let spare_power = reactor.power_per_second > 0.0 && !reactor.plant;
+            if power.ratio() == 0.0 && !spare_power { continue }
             for (res, count) in reactor.input.iter() {
                 if count == 0 { continue }
                 sink.has.dec_by(res, count).unwrap();
@@ -172,20 +222,21 @@ impl<'a> System<'a> for RunReactors {
 
 const WASTE_SPEED: f32 = 3.0;
 
-fn spawn_waste(lazy: &LazyUpdate, center: ::hex2d::Coordinate, res: Resource, count: usize) {
-    lazy.exec_mut(move |world| {
-        let mut rng = rand::thread_rng();
-        let targets = center.ring(5, hex2d::Spin::CW(hex2d::Direction::XY));
-        for _ in 0..count {
-            let ix: usize = rng.gen_range(0, targets.len());
-            let target = targets[ix];
-            world.create_entity()
-                .with(resource::Packet { resource: res })
-                .with(geom::Motion::new(center, target, WASTE_SPEED))
-                .with(Waste)
-                .build();
-        }
-    });
+pub fn spawn_waste(lazy: &LazyUpdate, center: ::hex2d::Coordinate, res: Resource, count: usize) {
+    lazy.exec_mut(move |world| make_waste(world, center, res, count));
+}
+
+pub fn make_waste(world: &mut World, center: ::hex2d::Coordinate, res: Resource, count: usize) {
+    let targets = center.ring(5, hex2d::Spin::CW(hex2d::Direction::XY));
+    for _ in 0..count {
+        let ix: usize = world.write_resource::<super::Rng>().0.gen_range(0, targets.len());
+        let target = targets[ix];
+        world.create_entity()
+            .with(resource::Packet { resource: res })
+            .with(geom::Motion::new(center, target, WASTE_SPEED))
+            .with(Waste)
+            .build();
+    }
 }
 
 #[derive(Debug)]
@@ -1,45 +1,33 @@
 use std::{
-    collections::HashMap,
-    mem::swap,
-    sync::mpsc::{channel, Sender},
+    cmp::{max, min, Reverse},
+    collections::{BTreeMap, BTreeSet, HashMap},
     time::{Duration, Instant},
 };
 
+use serde_derive::{Deserialize, Serialize};
 use specs::{
     prelude::*,
     storage::BTreeStorage,
 };
 
+use crate::defs::Defs;
 use crate::error::{
     Error, Result,
     or_die,
 };
 use crate::graph;
+use crate::reactor;
+use crate::save::{Loader, Persist, Saver};
 use crate::util::*;
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
-#[repr(usize)]
-pub enum Resource {
-    H2 = 0usize,
-    O2,
-    H2O,
-    C,
-    CO2,
-    CH4,
-}
+/// An index into the resources declared in the definitions file; names and
+/// colours live in `defs::Defs`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Resource(usize);
 
 impl Resource {
-    pub fn all() -> impl Iterator<Item=Resource> {
-        const ALL: [Resource; 6] = [
-            Resource::H2,
-            Resource::O2,
-            Resource::H2O,
-            Resource::C,
-            Resource::CO2,
-            Resource::CH4,
-        ];
-        ALL.iter().cloned()
-    }
+    pub fn from_index(ix: usize) -> Self { Resource(ix) }
+    pub fn index(self) -> usize { self.0 }
 }
 
 // Epiphany: `Source` and `Sink` are *just* the input/output buffers.
@@ -47,19 +35,21 @@ impl Resource {
 // Other behavior - production, reactor, etc. - are just inc/decs on
 // the Source/Sink numbers.
 
-#[derive(Debug, Clone)]
+/// Resources a pool hasn't been told about hold nothing and are capped at this.
+const DEFAULT_CAP: usize = 6;
+/// No pool can be capped above this.
+pub const MAX_CAP: usize = 999;
+
+/// Counts and caps grow on demand, so a pool never needs to know how many
+/// resources exist.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Pool {
-    count: [usize; 6],
-    cap: [usize; 6],
+    count: Vec<usize>,
+    cap: Vec<usize>,
 }
 
 impl Pool {
-    pub fn new() -> Self {
-        Pool {
-            count: [0, 0, 0, 0, 0, 0],
-            cap: [6, 6, 6, 6, 6, 6],
-        }
-    }
+    pub fn new() -> Self { Pool::default() }
     pub fn from<T>(t: T) -> Self
         where T: IntoIterator<Item=(Resource, usize)>
     {
@@ -69,7 +59,6 @@ impl Pool {
         }
         p
     }
-    #[allow(unused)]
     pub fn from_cap<R, C>(r: R, c: C) -> Self
         where R: IntoIterator<Item=(Resource, usize)>,
               C: IntoIterator<Item=(Resource, usize)>,
@@ -83,17 +72,25 @@ impl Pool {
         }
         p
     }
-    pub fn get(&self, res: Resource) -> usize { self.count[res as usize] }
-    pub fn cap(&self, res: Resource) -> usize { self.cap[res as usize] }
+    pub fn get(&self, res: Resource) -> usize { self.count.get(res.0).cloned().unwrap_or(0) }
+    pub fn cap(&self, res: Resource) -> usize { self.cap.get(res.0).cloned().unwrap_or(DEFAULT_CAP) }
+    fn slot(&mut self, res: Resource) -> usize {
+        while self.count.len() <= res.0 {
+            self.count.push(0);
+            self.cap.push(DEFAULT_CAP);
+        }
+        res.0
+    }
     fn do_cap(&self, res: Resource, count: usize) -> (usize, Option<usize>) {
-        let c = self.cap[res as usize];
+        let c = self.cap(res);
         if c < count {
             (c, Some(count - c))
         } else { (count, None) }
     }
     pub fn set(&mut self, res: Resource, count: usize) -> Option<usize> {
         let (c, o) = self.do_cap(res, count);
-        self.count[res as usize] = c;
+        let ix = self.slot(res);
+        self.count[ix] = c;
         o
     }
     pub fn inc(&mut self, res: Resource) -> Option<usize> { self.inc_by(res, 1) }
@@ -105,32 +102,36 @@ impl Pool {
         self.dec_by(res, 1)
     }
     pub fn dec_by(&mut self, res: Resource, count: usize) -> Result<()> {
-        if self.count[res as usize] >= count {
-            self.count[res as usize] -= count;
+        if self.get(res) >= count {
+            let ix = self.slot(res);
+            self.count[ix] -= count;
             return Ok(())
         }
         Err(Error::PoolUnderflow)
     }
-    #[allow(unused)]
     pub fn set_cap(&mut self, res: Resource, cap: usize) {
-        self.cap[res as usize] = cap;
+        debug_assert!(cap <= MAX_CAP, "cap of {} is over the limit of {}", cap, MAX_CAP);
+        let ix = self.slot(res);
+        self.cap[ix] = cap;
     }
     pub fn iter<'a>(&'a self) -> impl Iterator<Item=(Resource, usize)> + 'a {
-        self.count.iter().enumerate().map(|(u, &c)| (unsafe { ::std::mem::transmute::<usize, Resource>(u) }, c))
+        self.count.iter().enumerate().map(|(ix, &c)| (Resource(ix), c))
     }
-    pub fn str(&self) -> String {
+    pub fn str(&self, defs: &Defs) -> String {
         let mut parts = vec![];
         for (r, c) in self.iter() {
             if c == 0 { continue }
+            let name = &defs.res(r).name;
             parts.push(
-                if c == 1 { format!("{:?}", r) }
-                else { format!("{}{:?}", c, r) }
+                if c == 1 { name.clone() }
+                else { format!("{}{}", c, name) }
             );
         }
         parts.join("+")
     }
+    /// True when there's none of anything.
     pub fn is_empty(&self) -> bool {
-        self.iter().all(|(_, c)| c > 0)
+        self.iter().all(|(_, c)| c == 0)
     }
 }
 
@@ -148,17 +149,54 @@ impl Source {
             Ok(())
         });
     }
+    /// Drops send history for a sink that no longer exists.
+    pub fn forget(&mut self, sink: Entity) { self.last_send.remove(&sink); }
 }
 
 impl Component for Source {
     type Storage = DenseVecStorage<Self>;
 }
 
-#[derive(Debug)]
+#[derive(Serialize, Deserialize)]
+pub struct SourceData {
+    has: Pool,
+    last_send: Vec<(u32, Duration)>,
+}
+
+impl Persist for Source {
+    type Data = SourceData;
+    fn save(&self, saver: &Saver) -> Result<SourceData> {
+        let mut last_send = vec![];
+        for (&sink, &t) in &self.last_send {
+            last_send.push((saver.id(sink)?, saver.age(t)));
+        }
+        last_send.sort();
+        Ok(SourceData { has: self.has.clone(), last_send })
+    }
+    fn load(data: SourceData, loader: &Loader) -> Result<Self> {
+        let mut last_send = HashMap::new();
+        for (sink, age) in data.last_send {
+            last_send.insert(loader.entity(sink)?, loader.instant(age));
+        }
+        Ok(Source { has: data.has, last_send })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Sink {
     pub want: Pool,
     pub has: Pool,
     pub in_transit: Pool,
+    /// Sources serve higher priority sinks first, however far away.
+    pub priority: i32,
+}
+
+/// The range players can set a sink's priority within.
+pub const MIN_PRIORITY: i32 = -9;
+pub const MAX_PRIORITY: i32 = 9;
+
+pub fn clamp_priority(priority: i32) -> i32 {
+    max(MIN_PRIORITY, min(MAX_PRIORITY, priority))
 }
 
 impl Component for Sink {
@@ -169,11 +207,12 @@ impl Sink {
     pub fn new() -> Self {
         Sink {
             want: Pool::new(), has: Pool::new(), in_transit: Pool::new(),
+            priority: 0,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Packet {
     pub resource: Resource,
 }
@@ -191,6 +230,14 @@ impl Component for Target {
     type Storage = BTreeStorage<Self>;
 }
 
+impl Persist for Target {
+    type Data = u32;
+    fn save(&self, saver: &Saver) -> Result<u32> { saver.id(self.node) }
+    fn load(data: u32, loader: &Loader) -> Result<Self> {
+        Ok(Target { node: loader.entity(data)? })
+    }
+}
+
 /*
 #[derive(Debug)]
 pub struct SelfPull;
@@ -235,7 +282,7 @@ pub struct PullData<'a> {
     graphs: WriteStorage<'a, graph::AreaGraph>,
     sources: WriteStorage<'a, Source>,
     sinks: WriteStorage<'a, Sink>,
-    lazy: Read<'a, LazyUpdate>,
+    lazy: Write<'a, LazyUpdate>,
 }
 
 #[derive(Debug)]
@@ -252,12 +299,11 @@ fn pull_worker(
     links: &ReadStorage<graph::Link>,
     nodes: &ReadStorage<graph::Node>,
     now: &ReadExpect<super::Now>,
-    sender: &mut Sender<(Entity, Candidate)>,
     source_ent: Entity,
     source: &mut Source,
     ag: &mut graph::AreaGraph,
-) {
-    let mut candidates: Vec<(Entity, Candidate)> = vec![];
+) -> Option<(Entity, Candidate)> {
+    let mut candidates: Vec<(i32, Entity, Candidate)> = vec![];
     let (nodes_iter, mut router) = ag.nodes_route();
     for sink_ent in nodes_iter {
         let sink = if let Some(s) = sinks.get(sink_ent) { s } else { continue };
@@ -285,20 +331,14 @@ fn pull_worker(
                 } else { false }
             }
         };
-        candidates.push((sink_ent, Candidate {
+        candidates.push((sink.priority, sink_ent, Candidate {
             source: source_ent, route, route_time, on_cooldown,
         }));
     }
-    if candidates.is_empty() { return }
-    candidates.sort_unstable_by_key(|(_, c)| c.route_time);
-    let mut tmp = (source_ent, Candidate {
-        source: source_ent,
-        route: vec![],
-        route_time: Duration::from_millis(13),
-        on_cooldown: false,
-    });
-    swap(&mut tmp, &mut candidates[0]);
-    or_die(|| { sender.send(tmp).map_err(|_| Error::PullChannel)?; Ok(()) });
+    // Ties go to the lowest entity, so the choice doesn't depend on
+    // iteration order.
+    candidates.sort_unstable_by_key(|&(priority, sink_ent, ref c)| (Reverse(priority), c.route_time, sink_ent));
+    candidates.into_iter().next().map(|(_, sink_ent, c)| (sink_ent, c))
 }
 
 impl<'a> System<'a> for Pull {
@@ -310,13 +350,16 @@ impl<'a> System<'a> for Pull {
             let links = &data.links;
             let nodes = &data.nodes;
             let now = &data.now;
-            let (sender, receiver) = channel::<(Entity, Candidate)>();
-            (&*data.entities, &mut data.sources, &mut data.graphs).par_join().for_each_with(sender,
-                |sender, (source_ent, source, ag)| {
-                pull_worker(sinks, links, nodes, now, sender, source_ent, source, ag)
-            });
-            let mut sink_candidates = HashMap::<Entity, Vec<Candidate>>::new();
-            for (sink_ent, candidate) in receiver {
+            let found: Vec<(Entity, Candidate)> = (&*data.entities, &mut data.sources, &mut data.graphs)
+                .par_join()
+                .filter_map(|(source_ent, source, ag)| {
+                    pull_worker(sinks, links, nodes, now, source_ent, source, ag)
+                })
+                .collect();
+            // Sinks are visited in entity order, not in whatever order the
+            // workers happened to finish.
+            let mut sink_candidates = BTreeMap::<Entity, Vec<Candidate>>::new();
+            for (sink_ent, candidate) in found {
                 sink_candidates.entry(sink_ent)
                     .or_insert_with(|| vec![])
                     .push(candidate);
@@ -325,7 +368,7 @@ impl<'a> System<'a> for Pull {
         };
         for (sink_ent, mut candidates) in sink_candidates {
             if candidates.is_empty() { continue }
-            candidates.sort_unstable_by_key(|c| c.route_time);
+            candidates.sort_unstable_by_key(|c| (c.route_time, c.source));
             let candidate = &candidates[0];
             if candidate.on_cooldown { continue }
             let source = if let Some(s) = data.sources.get_mut(candidate.source) { s } else { continue };
@@ -340,17 +383,14 @@ impl<'a> System<'a> for Pull {
                 }
             }
             if can_pull.is_empty() { continue }
-            can_pull.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+            can_pull.sort_by(|a, b| b.1.cmp(&a.1));
             let pull_res = can_pull[0].0;
 
             source.last_send.insert(sink_ent, data.now.0);
             or_die(|| source.has.dec(pull_res));
             sink.in_transit.inc(pull_res);
 
-            let source_coord = {
-                let nodes = &data.nodes;
-                or_die(|| try_get(nodes, candidate.source)).at()
-            };
+            let source_ent = candidate.source;
             let route = candidate.route.clone();
             data.lazy.exec_mut(move |world| {
                 let packet = world.create_entity()
@@ -360,7 +400,9 @@ impl<'a> System<'a> for Pull {
                 graph::Traverse::start(
                     world,
                     packet,
-                    source_coord,
+                    source_ent,
+                    source_ent,
+                    sink_ent,
                     route,
                     PACKET_SPEED,
                 );
@@ -394,36 +436,64 @@ impl<'a> System<'a> for Receive {
     }
 }
 
-#[derive(Debug, Default)]
-pub struct Storage;
+/// Takes in whatever it accepts, up to its source's caps, and offers it back
+/// out again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Storage {
+    accept: BTreeSet<Resource>,
+}
+
+impl Storage {
+    /// Resources missing from `caps` can't be stored at all.
+    pub fn add(world: &mut World, entity: Entity, caps: &[(Resource, usize)], range: i32) {
+        let all: Vec<Resource> = world.read_resource::<Defs>().resources().collect();
+        let cap_of = |res| caps.iter().find(|&&(r, _)| r == res).map_or(0, |&(_, c)| c);
+        let pool = Pool::from_cap(vec![], all.iter().map(|&res| (res, cap_of(res))));
+        let accept = all.iter().cloned().filter(|&res| cap_of(res) > 0).collect();
+        Source::add(world, entity, pool, range);
+        or_die(|| {
+            world.write_storage().insert(entity, Sink::new())?;
+            world.write_storage().insert(entity, Storage { accept })?;
+            Ok(())
+        });
+    }
+    pub fn accepts(&self, res: Resource) -> bool { self.accept.contains(&res) }
+    pub fn set_accept(&mut self, res: Resource, on: bool) {
+        if on { self.accept.insert(res); } else { self.accept.remove(&res); }
+    }
+}
 
 impl Component for Storage {
-    type Storage = NullStorage<Self>;
+    type Storage = BTreeStorage<Self>;
 }
 
 #[derive(Debug)]
 pub struct DoStorage;
 
+type DoStorageData<'a> = (
+    ReadExpect<'a, Defs>,
+    ReadStorage<'a, graph::Node>,
+    ReadStorage<'a, Storage>,
+    WriteStorage<'a, Source>,
+    WriteStorage<'a, Sink>,
+    Write<'a, LazyUpdate>,
+);
+
 impl<'a> System<'a> for DoStorage {
-    type SystemData = (
-        ReadStorage<'a, Storage>,
-        WriteStorage<'a, Source>,
-        WriteStorage<'a, Sink>,
-    );
+    type SystemData = DoStorageData<'a>;
 
-    fn run(&mut self, (stores, mut sources, mut sinks): Self::SystemData) {
-        for (_, source, sink) in (stores.mask(), &mut sources, &mut sinks).join() {
-            for res in Resource::all() {
+    fn run(&mut self, (defs, nodes, stores, mut sources, mut sinks, lazy): Self::SystemData) {
+        for (node, store, source, sink) in (&nodes, &stores, &mut sources, &mut sinks).join() {
+            for res in defs.resources() {
                 let has = sink.has.get(res);
                 if has > 0 {
                     sink.has.set(res, 0);
-                    or_die(|| {
-                        source.has.inc_by(res, has)
-                            .map_or(Ok(()), |_| Err(Error::StorageOverflow))
-                    });
+                    if let Some(over) = source.has.inc_by(res, has) {
+                        reactor::spawn_waste(&lazy, node.at(), res, over);
+                    }
                 }
                 let pending = source.has.get(res);
-                let want = if source.has.cap(res) > pending {
+                let want = if store.accepts(res) && source.has.cap(res) > pending {
                     source.has.cap(res) - pending
                 } else { 0 };
                 if want != sink.want.get(res) {
@@ -432,4 +502,22 @@ impl<'a> System<'a> for DoStorage {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_is_empty() {
+        assert!(Pool::new().is_empty());
+        assert!(Pool::from(vec![(Resource(0), 0), (Resource(2), 0)]).is_empty());
+        assert!(!Pool::from(vec![(Resource(1), 1)]).is_empty());
+        // Not only when every resource is present.
+        assert!(!Pool::from(vec![(Resource(0), 0), (Resource(1), 3)]).is_empty());
+
+        let mut p = Pool::from(vec![(Resource(0), 2)]);
+        p.dec_by(Resource(0), 2).unwrap();
+        assert!(p.is_empty());
+    }
+}
@@ -0,0 +1,371 @@
+/*
+Save files are a RON document holding every persistent component, keyed by a
+dense entity id.  Entities are numbered in their current id order and
+recreated in that same order on load, so joins iterate the loaded world in the
+same sequence as the original and the simulation carries on identically.
+
+Derived indexes - `geom::Map`, `geom::AreaMap`, power networks - are not
+saved; they're rebuilt from the loaded components.
+*/
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use hex2d::Coordinate;
+use rand_xorshift::XorShiftRng;
+use serde_derive::{Deserialize, Serialize};
+use specs::prelude::*;
+
+use crate::blueprint;
+use crate::build;
+use crate::defs::{Defs, KindMap};
+use crate::draw;
+use crate::error::{Error, Result};
+use crate::game;
+use crate::geom;
+use crate::graph;
+use crate::power;
+use crate::reactor;
+use crate::resource;
+
+/// Bump this whenever the format changes incompatibly.
+pub const SAVE_VERSION: u32 = 1;
+
+pub const SAVE_PATH: &str = "tree-of-stars.sav";
+
+/// A component that can be written to and read from a save file.
+pub trait Persist: Component + Sized {
+    type Data: serde::Serialize + serde::de::DeserializeOwned;
+    fn save(&self, saver: &Saver) -> Result<Self::Data>;
+    fn load(data: Self::Data, loader: &Loader) -> Result<Self>;
+}
+
+pub struct Saver {
+    ids: HashMap<Entity, u32>,
+    now: Instant,
+}
+
+impl Saver {
+    pub fn id(&self, entity: Entity) -> Result<u32> {
+        self.ids.get(&entity).cloned().ok_or(Error::NoSuchEntity)
+    }
+    /// Instants are saved as their age relative to `Now`.
+    pub fn age(&self, t: Instant) -> Duration { self.now - t }
+}
+
+pub struct Loader {
+    entities: Vec<Entity>,
+    now: Instant,
+    kinds: KindMap,
+}
+
+impl Loader {
+    pub fn entity(&self, id: u32) -> Result<Entity> {
+        self.entities.get(id as usize).cloned().ok_or(Error::NoSuchEntity)
+    }
+    /// Kinds are saved as indexes into the definitions the game was using,
+    /// which may since have been reordered.
+    pub fn kind(&self, kind: build::Kind) -> Result<build::Kind> { self.kinds.get(kind) }
+    pub fn instant(&self, age: Duration) -> Instant { self.now - age }
+}
+
+pub type Coord = (i32, i32);
+
+pub fn to_coord(c: Coordinate) -> Coord { (c.x, c.y) }
+
+pub fn from_coord((x, y): Coord) -> Coordinate { Coordinate { x, y } }
+
+/// For components with no entity references, which are saved as-is.
+macro_rules! persist_plain {
+    ($($t:ty),* $(,)*) => {
+        $(
+            impl Persist for $t {
+                type Data = Self;
+                fn save(&self, _: &Saver) -> Result<Self> { Ok(self.clone()) }
+                fn load(data: Self, _: &Loader) -> Result<Self> { Ok(data) }
+            }
+        )*
+    }
+}
+
+persist_plain!(
+    geom::MotionDone,
+    graph::RouteDone,
+    graph::LinkRange,
+    resource::Sink,
+    resource::Packet,
+    resource::Storage,
+    reactor::Progress,
+    reactor::Waste,
+    power::Pylon,
+    power::Battery,
+    build::Pending,
+);
+
+macro_rules! components {
+    ($($name:ident: $t:ty),* $(,)*) => {
+        #[derive(Serialize, Deserialize)]
+        struct Components {
+            $($name: Vec<(u32, <$t as Persist>::Data)>,)*
+        }
+
+        impl Components {
+            fn save(world: &World, saver: &Saver) -> Result<Self> {
+                Ok(Components {
+                    $($name: save_storage::<$t>(world, saver)?,)*
+                })
+            }
+            fn load(self, world: &mut World, loader: &Loader) -> Result<()> {
+                $(load_storage::<$t>(world, loader, self.$name)?;)*
+                Ok(())
+            }
+        }
+    }
+}
+
+components! {
+    motion: geom::Motion,
+    motion_done: geom::MotionDone,
+    space: geom::Space,
+    area_set: geom::AreaSet,
+    node: graph::Node,
+    link: graph::Link,
+    area_graph: graph::AreaGraph,
+    follow_route: graph::FollowRoute,
+    route_done: graph::RouteDone,
+    link_range: graph::LinkRange,
+    source: resource::Source,
+    sink: resource::Sink,
+    packet: resource::Packet,
+    target: resource::Target,
+    storage: resource::Storage,
+    progress: reactor::Progress,
+    reactor: reactor::Reactor,
+    waste: reactor::Waste,
+    power: power::Power,
+    pylon: power::Pylon,
+    battery: power::Battery,
+    shape: draw::Shape,
+    grow_test: game::GrowTest,
+    pending: build::Pending,
+    build_packet: build::Packet,
+    factory: build::Factory,
+    delivery: build::Delivery,
+    planned: blueprint::Planned,
+}
+
+fn save_storage<T: Persist>(world: &World, saver: &Saver) -> Result<Vec<(u32, T::Data)>> {
+    let mut out = vec![];
+    for (entity, component) in (&*world.entities(), &world.read_storage::<T>()).join() {
+        out.push((saver.id(entity)?, component.save(saver)?));
+    }
+    Ok(out)
+}
+
+fn load_storage<T: Persist>(
+    world: &mut World, loader: &Loader, data: Vec<(u32, T::Data)>,
+) -> Result<()> {
+    let mut storage = world.write_storage::<T>();
+    for (id, d) in data {
+        storage.insert(loader.entity(id)?, T::load(d, loader)?)?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+struct SaveFile {
+    version: u32,
+    entities: u32,
+    kinds: Vec<String>,
+    resources: Vec<String>,
+    tick: u64,
+    paused: bool,
+    rng: XorShiftRng,
+    grid: Vec<(u32, u32)>,
+    components: Components,
+}
+
+pub fn save<P: AsRef<Path>>(world: &mut World, path: P) -> Result<()> {
+    world.maintain();
+    let saver = {
+        let mut ids = HashMap::new();
+        for entity in (&*world.entities()).join() {
+            let id = ids.len() as u32;
+            ids.insert(entity, id);
+        }
+        Saver { ids, now: world.read_resource::<super::Now>().0 }
+    };
+    let file = SaveFile {
+        version: SAVE_VERSION,
+        entities: saver.ids.len() as u32,
+        kinds: world.read_resource::<Defs>().names(),
+        resources: world.read_resource::<Defs>().res_names(),
+        tick: world.read_resource::<super::Tick>().0,
+        paused: world.read_resource::<super::Paused>().0,
+        rng: world.read_resource::<super::Rng>().0.clone(),
+        grid: world.read_resource::<power::PowerGrid>().save(&saver)?,
+        components: Components::save(world, &saver)?,
+    };
+    fs::write(path, ron::ser::to_string(&file)?)?;
+    Ok(())
+}
+
+pub fn load<P: AsRef<Path>>(path: P, defs: Defs) -> Result<World> {
+    let file: SaveFile = ron::de::from_str(&fs::read_to_string(path)?)?;
+    if file.version != SAVE_VERSION {
+        return Err(Error::SaveVersion(file.version))
+    }
+    defs.check_res(&file.resources)?;
+    let kinds = defs.remap(&file.kinds)?;
+    let mut world = super::new_world(defs);
+    let loader = Loader {
+        entities: (0..file.entities).map(|_| world.create_entity().build()).collect(),
+        now: world.read_resource::<super::Now>().0,
+        kinds,
+    };
+    world.write_resource::<super::Tick>().0 = file.tick;
+    world.write_resource::<super::Paused>().0 = file.paused;
+    world.write_resource::<super::Rng>().0 = file.rng;
+    *world.write_resource::<power::PowerGrid>() = power::PowerGrid::load(file.grid, &loader)?;
+    file.components.load(&mut world, &loader)?;
+    rebuild(&mut world);
+    Ok(world)
+}
+
+fn rebuild(world: &mut World) {
+    world.write_resource::<power::PowerGrid>().rebuild(
+        &world.entities(), &world.read_storage(), &world.read_storage(),
+        &world.read_storage(), &world.read_storage(),
+    );
+    *world.write_resource::<geom::Map>() = geom::Map::from_spaces(
+        &world.entities(), &world.read_storage(),
+    );
+    let mut areas = world.write_resource::<geom::AreaMap>();
+    let entities = world.entities();
+    let nodes = world.read_storage::<graph::Node>();
+    for (entity, node, area) in (&*entities, &nodes, &world.read_storage::<geom::AreaSet>()).join() {
+        areas.insert::<geom::AreaSet>(node.at(), area.range(), entity);
+    }
+    for (entity, node, area) in (&*entities, &nodes, &world.read_storage::<graph::AreaGraph>()).join() {
+        areas.insert::<graph::AreaGraph>(node.at(), area.range(), entity);
+    }
+}
+
+#[derive(Debug)]
+pub enum Request {
+    Save,
+    Load,
+}
+
+/// Set by the UI; the main loop carries it out between frames, since loading
+/// replaces the whole World.
+#[derive(Debug, Default)]
+pub struct Requested(pub Option<Request>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::command::{self, Command};
+    use crate::defs::DEFS_PATH;
+
+    fn temp(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tree-of-stars-{}-{}.sav", name, std::process::id()))
+    }
+
+    /// The save file is every piece of persistent state, so two worlds that
+    /// save the same are the same.
+    fn saved(world: &mut World, name: &str) -> String {
+        let path = temp(name);
+        save(world, &path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        text
+    }
+
+    fn start_building(world: &mut World) {
+        let seed = (&*world.entities(), &world.read_storage::<graph::Node>()).join().next().unwrap().0;
+        let kind = world.read_resource::<Defs>().find("CarbonSource").unwrap();
+        command::issue(world, Command::StartBuild {
+            factory: seed.into(), kind, fork: seed.into(), at: (0, 5),
+        });
+    }
+
+    #[test]
+    fn round_trip_carries_on_identically() {
+        let mut world = crate::test_world();
+        let mut update = crate::make_update();
+        start_building(&mut world);
+        for _ in 0..60 { crate::step(&mut world, &mut update); }
+
+        let path = temp("round-trip");
+        save(&mut world, &path).unwrap();
+        let mut loaded = load(&path, Defs::load(DEFS_PATH).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let mut loaded_update = crate::make_update();
+        for _ in 0..300 {
+            crate::step(&mut world, &mut update);
+            crate::step(&mut loaded, &mut loaded_update);
+        }
+        assert_eq!(saved(&mut world, "original"), saved(&mut loaded, "loaded"));
+    }
+
+    #[test]
+    fn load_rejects_empty_orders() {
+        let mut world = crate::test_world();
+        let seed = (&*world.entities(), &world.read_storage::<graph::Node>()).join().next().unwrap().0;
+        let strut = world.read_resource::<Defs>().find("Strut").unwrap();
+        {
+            let mut factories = world.write_storage::<build::Factory>();
+            let factory = factories.get_mut(seed).unwrap();
+            factory.queue_push(strut);
+            factory.set_count(0, Some(0));
+        }
+        let path = temp("empty-order");
+        save(&mut world, &path).unwrap();
+        let loaded = load(&path, Defs::load(DEFS_PATH).unwrap());
+        fs::remove_file(&path).unwrap();
+        match loaded {
+            Err(Error::InvalidSave(_)) => (),
+            other => panic!("expected an invalid save, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn load_remaps_reordered_kinds() {
+        let mut world = crate::test_world();
+        let mut update = crate::make_update();
+        start_building(&mut world);
+        for _ in 0..10 { crate::step(&mut world, &mut update); }
+        let path = temp("remap");
+        save(&mut world, &path).unwrap();
+
+        // Move the first kind to the end, shifting every other index down.
+        let text = fs::read_to_string(DEFS_PATH).unwrap();
+        let defs = Defs::parse_rotated(&text, 1).unwrap();
+        assert_ne!(defs.names(), world.read_resource::<Defs>().names());
+
+        let loaded = load(&path, defs).unwrap();
+        fs::remove_file(&path).unwrap();
+        let names = |world: &World| -> Vec<(String, Vec<String>, usize)> {
+            let defs = world.read_resource::<Defs>();
+            let source = defs.find("CarbonSource").unwrap();
+            let mut out: Vec<_> = world.read_storage::<build::Factory>().join().map(|f| {
+                let mut builds: Vec<String> = f.can_build().iter().map(|&k| defs.name(k).to_owned()).collect();
+                builds.sort();
+                (defs.name(defs.start()).to_owned(), builds, f.built(source))
+            }).collect();
+            for packet in world.read_storage::<build::Packet>().join() {
+                out.push((defs.name(packet.kind()).to_owned(), vec![], 0));
+            }
+            out
+        };
+        let before = names(&world);
+        assert_eq!(before.len(), 2);
+        assert_eq!(before, names(&loaded));
+    }
+}
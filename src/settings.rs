@@ -0,0 +1,74 @@
+/*
+Player preferences that aren't part of the game: how the window is set up,
+and which keys do what.  They're read once at startup from a RON file; a
+missing file, or a field left out of it, means the default.
+*/
+
+use std::{
+    fs,
+    io,
+    path::Path,
+};
+
+use ggez::conf;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::input;
+
+pub const SETTINGS_PATH: &str = "settings.ron";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Window size in pixels, when not fullscreen.
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+    /// MSAA samples: 1, 2, 4, 8 or 16.
+    pub samples: u32,
+    /// Changes to the default key and mouse bindings; see `input`.
+    pub bindings: Vec<(input::Action, Vec<input::Binding>)>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings { width: 800, height: 800, fullscreen: false, samples: 8, bindings: vec![] }
+    }
+}
+
+impl Settings {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(t) => t,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Settings::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let settings: Settings = ron::de::from_str(&text)?;
+        if settings.width == 0 || settings.height == 0 {
+            return Err(Error::Settings("window size must be positive".into()))
+        }
+        settings.num_samples()?;
+        Ok(settings)
+    }
+
+    pub fn num_samples(&self) -> Result<conf::NumSamples> {
+        conf::NumSamples::from_u32(self.samples)
+            .ok_or_else(|| Error::Settings(format!("{} is not a valid sample count", self.samples)))
+    }
+
+    pub fn conf(&self) -> Result<conf::Conf> {
+        let mut c = conf::Conf::default();
+        c.window_setup.title = "Tree of Stars".to_owned();
+        c.window_setup.samples = self.num_samples()?;
+        c.window_setup.resizable = true;
+        c.window_mode.width = self.width;
+        c.window_mode.height = self.height;
+        c.window_mode.fullscreen_type = if self.fullscreen {
+            conf::FullscreenType::Desktop
+        } else {
+            conf::FullscreenType::Off
+        };
+        Ok(c)
+    }
+}